@@ -0,0 +1,128 @@
+use std::fmt::Debug;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rollback::{FrameInputs, RepeatLastInput, RollbackStateManager, SaveState};
+
+type Manager<State> = RollbackStateManager<u8, State, RepeatLastInput, u32>;
+type InputFn = fn(usize, u32) -> u8;
+
+const MAX_ROLLBACK: usize = 32;
+// Frames between a remote input being pressed and arriving in the input storage benchmarks
+const LATENCY: usize = 6;
+
+fn update(inputs: &FrameInputs<u8, u32>, state: u64) -> u64 {
+    inputs.values().fold(state, |state, input| state.wrapping_mul(31).wrapping_add(*input as u64))
+}
+
+// Touches a few bytes so the cost is dominated by copying the state for snapshots and rollbacks
+fn update_large(inputs: &FrameInputs<u8, u32>, state: &mut [u8]) {
+    let len = state.len();
+    for (index, input) in inputs.values().enumerate() {
+        state[index % len] = state[index % len].wrapping_add(*input);
+    }
+}
+
+// Remote inputs flip every frame, so repeating the last input always mispredicts and each late
+// input rolls back to its frame
+fn flipping_input(frame: usize, player: u32) -> u8 {
+    ((frame + player as usize) % 2) as u8
+}
+
+// Remote players change input every few frames, so most late inputs confirm the prediction and
+// some cause a rollback
+fn held_input(frame: usize, player: u32) -> u8 {
+    ((frame / 4 + player as usize) % 3) as u8
+}
+
+// Add the next frame's inputs for every player, with remote inputs arriving the given number of
+// frames late
+fn handle_inputs<State: SaveState + Debug>(manager: &mut Manager<State>, players: u32, delay: usize, input: InputFn) {
+    let frame = manager.current_frame_index + 1;
+    manager.handle_input(frame, 0, input(frame, 0)).unwrap();
+    if let Some(remote_frame) = frame.checked_sub(delay) {
+        for player in 1..players {
+            manager.handle_input(remote_frame, player, input(remote_frame, player)).unwrap();
+        }
+    }
+}
+
+fn tick(manager: &mut Manager<u64>, players: u32, delay: usize, input: InputFn) {
+    handle_inputs(manager, players, delay, input);
+    manager.progress_frame(update).unwrap();
+}
+
+fn warmed_up_manager(players: u32, delay: usize, input: InputFn) -> Manager<u64> {
+    let mut manager = Manager::new(0, MAX_ROLLBACK);
+    for player in 0..players {
+        manager.handle_input(0, player, input(0, player)).unwrap();
+    }
+    for _ in 0..MAX_ROLLBACK * 2 {
+        tick(&mut manager, players, delay, input);
+    }
+    manager
+}
+
+fn tick_large(manager: &mut Manager<Vec<u8>>) {
+    handle_inputs(manager, 2, 4, flipping_input);
+    manager.progress_frame_mut(|inputs, state: &mut Vec<u8>| update_large(inputs, state)).unwrap();
+}
+
+// Every input arrives before its frame is simulated, so no frame is ever simulated twice
+fn bench_steady_state(c: &mut Criterion) {
+    c.bench_function("progress_frame/steady_state", |b| {
+        let mut manager = warmed_up_manager(2, 0, flipping_input);
+        b.iter(|| tick(&mut manager, 2, 0, flipping_input));
+    });
+}
+
+// Each tick rolls back and simulates again as many frames as the remote input is late
+fn bench_late_inputs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("progress_frame/late_input");
+    for depth in [1, 4, 16] {
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &depth, |b, &depth| {
+            let mut manager = warmed_up_manager(2, depth, flipping_input);
+            b.iter(|| tick(&mut manager, 2, depth, flipping_input));
+        });
+    }
+    group.finish();
+}
+
+// Storing and looking up inputs as the number of players grows
+fn bench_input_storage(c: &mut Criterion) {
+    let mut group = c.benchmark_group("input_storage/tick");
+    for players in [2, 8, 64] {
+        group.bench_with_input(BenchmarkId::from_parameter(players), &players, |b, &players| {
+            let mut manager = warmed_up_manager(players, LATENCY, held_input);
+            b.iter(|| tick(&mut manager, players, LATENCY, held_input));
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("input_storage/get_frame_inputs");
+    for players in [2, 8, 64] {
+        group.bench_with_input(BenchmarkId::from_parameter(players), &players, |b, &players| {
+            let manager = warmed_up_manager(players, LATENCY, held_input);
+            let frame = manager.current_frame_index;
+            b.iter(|| black_box(manager.get_frame_inputs(black_box(frame))));
+        });
+    }
+    group.finish();
+}
+
+// Snapshots copy the state every frame and rollbacks copy it back, through SaveState
+fn bench_large_state(c: &mut Criterion) {
+    let mut group = c.benchmark_group("progress_frame_mut/state_bytes");
+    for size in [1 << 10, 1 << 16, 1 << 20] {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            let mut manager: Manager<Vec<u8>> = Manager::new(vec![0; size], MAX_ROLLBACK);
+            for _ in 0..MAX_ROLLBACK * 2 {
+                tick_large(&mut manager);
+            }
+            b.iter(|| tick_large(&mut manager));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_steady_state, bench_late_inputs, bench_input_storage, bench_large_state);
+criterion_main!(benches);
@@ -0,0 +1,63 @@
+use std::hash::{Hash, Hasher};
+
+// Summarizes a game state so that peers can detect when their simulations have diverged. Equal
+// states must produce equal checksums on every peer and platform
+pub trait Checksum {
+    fn checksum(&self) -> u64;
+}
+
+// 64 bit FNV-1a hasher. Unlike the standard library's default hasher, its output is stable across
+// processes, platforms and compiler versions
+#[derive(Debug, Clone, Copy)]
+pub struct FnvHasher(u64);
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+impl Default for FnvHasher {
+    fn default() -> FnvHasher {
+        FnvHasher(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    // Integers are hashed as little endian so results don't depend on the platform
+    fn write_usize(&mut self, value: usize) {
+        self.write(&(value as u64).to_le_bytes());
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, value: u128) {
+        self.write(&value.to_le_bytes());
+    }
+}
+
+// Checksum any hashable value with a stable hasher. Convenient for implementing Checksum on states
+// whose Hash implementation is deterministic
+pub fn hash_checksum<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = FnvHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,271 @@
+use core::slice;
+use std::iter::FromIterator;
+use std::vec;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::DefaultPlayerId;
+
+// Where a player's input for a frame came from. Frames aren't simulated again when a prediction
+// is confirmed with the same input, so peers can simulate a frame with different statuses. Only
+// use confirmed and predicted statuses for effects outside the state, like sounds and particles.
+// Progress reports which frames have since been confirmed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum InputStatus {
+    // Received from the player for this frame
+    Confirmed,
+    // Guessed by the predictor because the input hasn't been received yet, or never was before
+    // the frame left the rollback window
+    Predicted,
+    // Guessed by the predictor because the player has disconnected and won't send any more
+    Disconnected
+}
+
+// Every player's input for a single frame along with its status, ordered by player id. Unlike a
+// HashMap, iterating gives the same order on every peer, so game logic which loops over players
+// stays deterministic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameInputs<Input, Id = DefaultPlayerId> {
+    // Sorted by id with no id repeated
+    inputs: Vec<(Id, Input, InputStatus)>
+}
+
+impl<Input, Id> FrameInputs<Input, Id> {
+    pub fn new() -> FrameInputs<Input, Id> {
+        FrameInputs {
+            inputs: Vec::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    // Iterate over players and their inputs in id order
+    pub fn iter(&self) -> FrameInputsIter<'_, Input, Id> {
+        FrameInputsIter {
+            inputs: self.inputs.iter()
+        }
+    }
+
+    // Iterate over players, their inputs and where the inputs came from in id order
+    pub fn iter_with_status(&self) -> impl DoubleEndedIterator<Item = (&Id, &Input, InputStatus)> + ExactSizeIterator {
+        self.inputs.iter().map(|(id, input, status)| (id, input, *status))
+    }
+
+    pub fn ids(&self) -> impl DoubleEndedIterator<Item = &Id> + ExactSizeIterator {
+        self.inputs.iter().map(|(id, _, _)| id)
+    }
+
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &Input> + ExactSizeIterator {
+        self.inputs.iter().map(|(_, input, _)| input)
+    }
+
+    // Whether any input still has to be received, meaning the frame may be simulated again
+    pub fn has_predictions(&self) -> bool {
+        self.inputs.iter().any(|(_, _, status)| *status == InputStatus::Predicted)
+    }
+}
+
+impl<Input, Id: Ord> FrameInputs<Input, Id> {
+    fn position(&self, id: &Id) -> Result<usize, usize> {
+        self.inputs.binary_search_by(|(other_id, _, _)| other_id.cmp(id))
+    }
+
+    pub fn get(&self, id: &Id) -> Option<&Input> {
+        self.position(id).ok().map(|index| &self.inputs[index].1)
+    }
+
+    pub fn status(&self, id: &Id) -> Option<InputStatus> {
+        self.position(id).ok().map(|index| self.inputs[index].2)
+    }
+
+    pub fn contains_key(&self, id: &Id) -> bool {
+        self.position(id).is_ok()
+    }
+
+    // Set a player's confirmed input, returning the input it replaced
+    pub fn insert(&mut self, id: Id, input: Input) -> Option<Input> {
+        self.insert_with_status(id, input, InputStatus::Confirmed)
+    }
+
+    pub fn insert_with_status(&mut self, id: Id, input: Input, status: InputStatus) -> Option<Input> {
+        match self.position(&id) {
+            Ok(index) => {
+                self.inputs[index].2 = status;
+                Some(std::mem::replace(&mut self.inputs[index].1, input))
+            },
+            Err(index) => {
+                self.inputs.insert(index, (id, input, status));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: &Id) -> Option<Input> {
+        self.position(id).ok().map(|index| self.inputs.remove(index).1)
+    }
+}
+
+impl<Input, Id> Default for FrameInputs<Input, Id> {
+    fn default() -> FrameInputs<Input, Id> {
+        FrameInputs::new()
+    }
+}
+
+// Sorts the inputs by id. Like collecting into a map, the last input given for an id wins
+impl<Input, Id: Ord> From<Vec<(Id, Input, InputStatus)>> for FrameInputs<Input, Id> {
+    fn from(mut inputs: Vec<(Id, Input, InputStatus)>) -> FrameInputs<Input, Id> {
+        inputs.reverse();
+        inputs.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+        inputs.dedup_by(|(a, _, _), (b, _, _)| a == b);
+        FrameInputs {
+            inputs
+        }
+    }
+}
+
+// Inputs without a status are treated as confirmed
+impl<Input, Id: Ord> From<Vec<(Id, Input)>> for FrameInputs<Input, Id> {
+    fn from(inputs: Vec<(Id, Input)>) -> FrameInputs<Input, Id> {
+        inputs.into_iter().collect()
+    }
+}
+
+// Serialized as a list of id, input and status triples, sorted again when deserializing
+#[cfg(feature = "serde")]
+impl<Input: Serialize, Id: Serialize> Serialize for FrameInputs<Input, Id> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.inputs.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, Input: Deserialize<'de>, Id: Ord + Deserialize<'de>> Deserialize<'de> for FrameInputs<Input, Id> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<FrameInputs<Input, Id>, D::Error> {
+        Vec::<(Id, Input, InputStatus)>::deserialize(deserializer).map(FrameInputs::from)
+    }
+}
+
+impl<Input, Id: Ord> FromIterator<(Id, Input, InputStatus)> for FrameInputs<Input, Id> {
+    fn from_iter<I: IntoIterator<Item = (Id, Input, InputStatus)>>(iter: I) -> FrameInputs<Input, Id> {
+        FrameInputs::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl<Input, Id: Ord> FromIterator<(Id, Input)> for FrameInputs<Input, Id> {
+    fn from_iter<I: IntoIterator<Item = (Id, Input)>>(iter: I) -> FrameInputs<Input, Id> {
+        iter.into_iter().map(|(id, input)| (id, input, InputStatus::Confirmed)).collect()
+    }
+}
+
+impl<Input, Id> IntoIterator for FrameInputs<Input, Id> {
+    type Item = (Id, Input);
+    type IntoIter = FrameInputsIntoIter<Input, Id>;
+
+    fn into_iter(self) -> FrameInputsIntoIter<Input, Id> {
+        FrameInputsIntoIter {
+            inputs: self.inputs.into_iter()
+        }
+    }
+}
+
+impl<'a, Input, Id> IntoIterator for &'a FrameInputs<Input, Id> {
+    type Item = (&'a Id, &'a Input);
+    type IntoIter = FrameInputsIter<'a, Input, Id>;
+
+    fn into_iter(self) -> FrameInputsIter<'a, Input, Id> {
+        self.iter()
+    }
+}
+
+// Iterator over players and their inputs in id order
+#[derive(Debug, Clone)]
+pub struct FrameInputsIter<'a, Input, Id> {
+    inputs: slice::Iter<'a, (Id, Input, InputStatus)>
+}
+
+impl<'a, Input, Id> Iterator for FrameInputsIter<'a, Input, Id> {
+    type Item = (&'a Id, &'a Input);
+
+    fn next(&mut self) -> Option<(&'a Id, &'a Input)> {
+        self.inputs.next().map(|(id, input, _)| (id, input))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inputs.size_hint()
+    }
+}
+
+impl<'a, Input, Id> DoubleEndedIterator for FrameInputsIter<'a, Input, Id> {
+    fn next_back(&mut self) -> Option<(&'a Id, &'a Input)> {
+        self.inputs.next_back().map(|(id, input, _)| (id, input))
+    }
+}
+
+impl<'a, Input, Id> ExactSizeIterator for FrameInputsIter<'a, Input, Id> {}
+
+// Owning iterator over players and their inputs in id order
+#[derive(Debug, Clone)]
+pub struct FrameInputsIntoIter<Input, Id> {
+    inputs: vec::IntoIter<(Id, Input, InputStatus)>
+}
+
+impl<Input, Id> Iterator for FrameInputsIntoIter<Input, Id> {
+    type Item = (Id, Input);
+
+    fn next(&mut self) -> Option<(Id, Input)> {
+        self.inputs.next().map(|(id, input, _)| (id, input))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inputs.size_hint()
+    }
+}
+
+impl<Input, Id> DoubleEndedIterator for FrameInputsIntoIter<Input, Id> {
+    fn next_back(&mut self) -> Option<(Id, Input)> {
+        self.inputs.next_back().map(|(id, input, _)| (id, input))
+    }
+}
+
+impl<Input, Id> ExactSizeIterator for FrameInputsIntoIter<Input, Id> {}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn FromIterator_UnorderedIds_IteratesInIdOrder() {
+        let inputs: FrameInputs<&str, u8> = vec![(7, "a"), (2, "b"), (9, "c"), (2, "d")].into_iter().collect();
+
+        assert_eq!(inputs.len(), 3);
+        assert_eq!(inputs.iter().collect::<Vec<_>>(), vec![(&2, &"d"), (&7, &"a"), (&9, &"c")]);
+        assert_eq!(inputs.get(&9), Some(&"c"));
+        assert_eq!(inputs.get(&3), None);
+    }
+
+    #[test]
+    fn Insert_Remove_KeepsIdOrder() {
+        let mut inputs = FrameInputs::new();
+        assert_eq!(inputs.insert(5u32, 50u8), None);
+        assert_eq!(inputs.insert_with_status(1, 10, InputStatus::Predicted), None);
+        assert_eq!(inputs.insert(3, 30), None);
+        assert!(inputs.has_predictions());
+        assert_eq!(inputs.insert(1, 11), Some(10));
+        assert!(!inputs.has_predictions());
+        assert_eq!(inputs.remove(&3), Some(30));
+        assert_eq!(inputs.remove(&3), None);
+
+        assert_eq!(inputs.ids().copied().collect::<Vec<_>>(), vec![1, 5]);
+        assert_eq!(inputs.values().copied().collect::<Vec<_>>(), vec![11, 50]);
+        assert_eq!(inputs.status(&1), Some(InputStatus::Confirmed));
+    }
+}
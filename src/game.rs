@@ -0,0 +1,208 @@
+use core::fmt::Debug;
+use crate::{DefaultPlayerId, FrameInputs, InputPredictor, PlayerId, Progress, RepeatLastInput, Result, RollbackStateManager, SaveState};
+
+// A game simulated by a rollback session. The game object can carry context which doesn't belong
+// in the rolled back state, such as asset handles or scratch buffers, but anything affecting the
+// outcome of a frame must live in the state
+pub trait RollbackGame {
+    type Input: Eq + Clone + Debug;
+    type State: SaveState + Debug;
+    type Id: PlayerId;
+
+    // Simulate one frame, changing the state in place
+    fn advance(&mut self, inputs: &FrameInputs<Self::Input, Self::Id>, state: &mut Self::State);
+
+    // Copy a state for a snapshot. Defaults to SaveState, which Clone states get through Clone, so
+    // games can override this and load with a cheaper strategy for their states
+    fn save(&mut self, state: &Self::State) -> Self::State {
+        state.save_state()
+    }
+
+    // Overwrite a state with a snapshot when rolling back
+    fn load(&mut self, state: &mut Self::State, saved: &Self::State) {
+        state.load_state(saved);
+    }
+
+    // Summary of a state compared between peers to detect desyncs. Games without one don't record
+    // checksums unless they're enabled on the state manager
+    fn checksum(&self, _state: &Self::State) -> Option<u64> {
+        None
+    }
+}
+
+// How the state manager simulates frames and copies states while progressing in place
+pub(crate) trait FrameSimulation<Input, State, Id = DefaultPlayerId> {
+    fn advance(&mut self, inputs: &FrameInputs<Input, Id>, state: &mut State);
+    fn save(&mut self, state: &State) -> State;
+    fn load(&mut self, state: &mut State, saved: &State);
+
+    // Checksum recorded instead of the state manager's own
+    fn checksum(&self, _state: &State) -> Option<u64> {
+        None
+    }
+}
+
+// Update function which changes the state in place, with states copied through SaveState
+pub(crate) struct InPlaceUpdate<F>(pub F);
+
+impl<Input, State: SaveState, Id, F: FnMut(&FrameInputs<Input, Id>, &mut State)> FrameSimulation<Input, State, Id> for InPlaceUpdate<F> {
+    fn advance(&mut self, inputs: &FrameInputs<Input, Id>, state: &mut State) {
+        (self.0)(inputs, state);
+    }
+
+    fn save(&mut self, state: &State) -> State {
+        state.save_state()
+    }
+
+    fn load(&mut self, state: &mut State, saved: &State) {
+        state.load_state(saved);
+    }
+}
+
+pub(crate) struct GameUpdate<'a, Game>(pub &'a mut Game);
+
+impl<'a, Game: RollbackGame> FrameSimulation<Game::Input, Game::State, Game::Id> for GameUpdate<'a, Game> {
+    fn advance(&mut self, inputs: &FrameInputs<Game::Input, Game::Id>, state: &mut Game::State) {
+        self.0.advance(inputs, state);
+    }
+
+    fn save(&mut self, state: &Game::State) -> Game::State {
+        self.0.save(state)
+    }
+
+    fn load(&mut self, state: &mut Game::State, saved: &Game::State) {
+        self.0.load(state, saved);
+    }
+
+    fn checksum(&self, state: &Game::State) -> Option<u64> {
+        self.0.checksum(state)
+    }
+}
+
+// State manager paired with the game it simulates, so frames are progressed without passing an
+// update function every time. Checksums are recorded with the game's checksum, if it has one
+pub struct GameSession<Game: RollbackGame, Predictor: InputPredictor<Game::Input, Game::Id> = RepeatLastInput> {
+    pub manager: RollbackStateManager<Game::Input, Game::State, Predictor, Game::Id>,
+    pub game: Game
+}
+
+impl<Game: RollbackGame, Predictor: InputPredictor<Game::Input, Game::Id>> GameSession<Game, Predictor> {
+    pub fn new(manager: RollbackStateManager<Game::Input, Game::State, Predictor, Game::Id>, game: Game) -> GameSession<Game, Predictor> {
+        GameSession {
+            manager,
+            game
+        }
+    }
+
+    // Progress the state manager by a frame
+    pub fn advance(&mut self) -> Result<Progress<Game::Id>> {
+        self.manager.progress_game(&mut self.game)
+    }
+
+    pub fn handle_input(&mut self, frame: usize, id: Game::Id, input: Game::Input) -> Result<()> {
+        self.manager.handle_input(frame, id, input)
+    }
+
+    pub fn state(&self) -> &Game::State {
+        &self.manager.current_frame_state
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use crate::{hash_checksum, SplitMix64};
+
+    // Keeps a random number generator in the game object. Its output only decides how the state
+    // is laid out, not what it contains, so it can't cause desyncs
+    struct Scoreboard {
+        scratch: Vec<u64>,
+        random: SplitMix64,
+        saves: usize,
+        loads: usize
+    }
+
+    impl RollbackGame for Scoreboard {
+        type Input = u8;
+        type State = Vec<u64>;
+        type Id = u32;
+
+        fn advance(&mut self, inputs: &FrameInputs<u8, u32>, state: &mut Vec<u64>) {
+            self.scratch.clear();
+            self.scratch.extend(inputs.values().map(|input| *input as u64));
+            self.scratch.sort();
+            if self.random.chance(0.5) {
+                self.scratch.reverse();
+            }
+            state.push(self.scratch.iter().sum::<u64>() + state.last().copied().unwrap_or(0));
+        }
+
+        fn save(&mut self, state: &Vec<u64>) -> Vec<u64> {
+            self.saves += 1;
+            state.clone()
+        }
+
+        fn load(&mut self, state: &mut Vec<u64>, saved: &Vec<u64>) {
+            self.loads += 1;
+            state.clone_from(saved);
+        }
+
+        fn checksum(&self, state: &Vec<u64>) -> Option<u64> {
+            Some(hash_checksum(state))
+        }
+    }
+
+    #[test]
+    fn Advance_LateInput_RollsBackThroughGame() -> Result<()> {
+        let p1 = 1;
+        let p2 = 2;
+        let game = Scoreboard { scratch: Vec::new(), random: SplitMix64::new(3), saves: 0, loads: 0 };
+        let mut session = GameSession::new(RollbackStateManager::new(Vec::new(), 8), game);
+
+        for frame in 1..6 {
+            session.handle_input(frame, p1, frame as u8)?;
+            session.advance()?;
+        }
+        assert_eq!(session.game.loads, 1);
+
+        session.handle_input(3, p2, 10)?;
+        let report = session.advance()?.into_rollback();
+        assert_eq!(report.map(|report| report.earliest_mispredicted_frame), Some(3));
+        assert_eq!(session.game.loads, 2);
+        assert!(session.game.saves >= 7);
+
+        // Player 2's input is repeated from frame 3 onward
+        assert_eq!(session.state(), &vec![0, 1, 3, 16, 30, 45, 60]);
+        assert_eq!(session.manager.frame_checksums.get(&6), Some(&hash_checksum(session.state())));
+
+        Ok(())
+    }
+    // Game which only advances, without a checksum of its own
+    struct Counter;
+
+    impl RollbackGame for Counter {
+        type Input = u8;
+        type State = u64;
+        type Id = u32;
+
+        fn advance(&mut self, inputs: &FrameInputs<u8, u32>, state: &mut u64) {
+            *state += inputs.values().map(|input| *input as u64).sum::<u64>();
+        }
+    }
+
+    #[test]
+    fn Advance_GameWithoutChecksum_RecordsNoChecksums() -> Result<()> {
+        let mut session = GameSession::new(RollbackStateManager::new(0, 8), Counter);
+        for frame in 0..4 {
+            session.handle_input(frame, 1, 2)?;
+            session.advance()?;
+        }
+
+        // Frames 0 through 4, with the input of frame 4 predicted
+        assert_eq!(session.state(), &10);
+        assert!(session.manager.frame_checksums.is_empty());
+
+        Ok(())
+    }
+}
@@ -1,16 +1,81 @@
 use core::fmt::Debug;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::error;
 use std::fmt;
-use uuid::Uuid;
+use std::ops::Range;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+mod checksum;
+mod frame_inputs;
+mod game;
+mod player_id;
+mod player_inputs;
+mod predictor;
+mod replay;
+mod save_state;
+mod session;
+mod simulated;
+mod snapshot;
+mod spectator;
+mod sync_test;
+#[cfg(test)]
+mod test_helpers;
+mod time_sync;
+mod wire;
+
+use game::{FrameSimulation, GameUpdate, InPlaceUpdate};
+
+pub use checksum::{hash_checksum, Checksum, FnvHasher};
+pub use frame_inputs::{FrameInputs, FrameInputsIntoIter, FrameInputsIter, InputStatus};
+pub use game::{GameSession, RollbackGame};
+pub use player_id::{DefaultPlayerId, PlayerId};
+pub use player_inputs::PlayerInputs;
+pub use predictor::{DefaultInput, InputHistory, InputPredictor, RepeatLastInput};
+pub use replay::Replay;
+pub use save_state::SaveState;
+pub use session::{bind_udp_socket, P2PSession, Peer, Socket, SpectatorPeer};
+pub use simulated::{NetworkConditions, SimulatedNetwork, SimulatedSocket, SplitMix64};
+pub use snapshot::SnapshotBuffer;
+pub use spectator::{Spectator, SpectatorSession};
+pub use sync_test::SyncTestSession;
+pub use time_sync::{TimeSync, TimeSyncRecommendation, MIN_FRAME_ADVANTAGE};
+pub use wire::{read_varint, write_varint, ConfirmedFrames, InputBatch, InputCodec, RedundantInputQueue, VarintCodec, WirePlayerId, MAX_DATAGRAM_SIZE, WIRE_VERSION};
 
 pub type Result<T> = std::result::Result<T, RollbackError>;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum RollbackError {
     InputTooOld {
         input_frame: usize,
         oldest_valid_frame: usize
+    },
+    PlayerEventTooOld {
+        event_frame: usize,
+        oldest_valid_frame: usize
+    },
+    // Simulating a frame again from the same state and inputs gave a different result
+    NonDeterministic {
+        frame: usize,
+        original: u64,
+        resimulated: u64
+    },
+    // A peer's checksum differed from the local one for a confirmed frame
+    Desync {
+        frame: usize,
+        local: u64,
+        remote: u64
+    },
+    UnsupportedVersion {
+        version: u8
+    },
+    MalformedMessage {
+        reason: String
+    },
+    Network {
+        reason: String
     }
 }
 
@@ -19,6 +84,24 @@ impl fmt::Display for RollbackError {
         match self {
             RollbackError::InputTooOld { input_frame, oldest_valid_frame } => {
                 write!(f, "Input for frame {} is older than oldest valid frame of {}", input_frame, oldest_valid_frame)
+            },
+            RollbackError::PlayerEventTooOld { event_frame, oldest_valid_frame } => {
+                write!(f, "Player event for frame {} is older than oldest valid frame of {}", event_frame, oldest_valid_frame)
+            },
+            RollbackError::NonDeterministic { frame, original, resimulated } => {
+                write!(f, "Frame {} was non deterministic with checksum {:016x} and {:016x} when simulated again", frame, original, resimulated)
+            },
+            RollbackError::Desync { frame, local, remote } => {
+                write!(f, "Frame {} desynced with local checksum {:016x} and remote checksum {:016x}", frame, local, remote)
+            },
+            RollbackError::UnsupportedVersion { version } => {
+                write!(f, "Message has unsupported wire format version {}", version)
+            },
+            RollbackError::MalformedMessage { reason } => {
+                write!(f, "Malformed message: {}", reason)
+            },
+            RollbackError::Network { reason } => {
+                write!(f, "Network error: {}", reason)
             }
         }
     }
@@ -31,83 +114,724 @@ impl error::Error for RollbackError {
     }
 }
 
-pub struct RollbackStateManager<Input: Eq + Clone + Debug, State: Clone + Debug> {
+// Change to the set of players taking part in the game, starting at the frame it was recorded for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum PlayerEvent {
+    Joined,
+    Left,
+    // Still part of the game with predicted inputs, but no longer holding back confirmation from
+    // that frame on
+    Disconnected
+}
+
+// Outcome of progressing a frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Progress<Id = DefaultPlayerId> {
+    // The frame was simulated, along with a report if mispredicted inputs caused previous frames to
+    // be simulated again
+    Advanced {
+        rollback: Option<RollbackReport<Id>>,
+        // Frames which became confirmed since the last progress, now simulated with every
+        // player's real inputs. Frames which left the rollback window unconfirmed are skipped
+        confirmed_frames: Range<usize>,
+        // First pending peer checksum which differed from the local one once its frame was confirmed
+        desync: Option<Desync>
+    },
+    // Progressing would push unconfirmed frames out of the rollback window. Nothing was simulated
+    WaitingOnPlayers {
+        frame: usize,
+        // Players whose inputs are needed first, sorted by id
+        players: Vec<Id>
+    },
+    // A session is holding back so its peers can catch up. Nothing was simulated
+    HoldingBack {
+        frame: usize,
+        frames_ahead: usize
+    }
+}
+
+impl<Id> Progress<Id> {
+    // Whether nothing was simulated, either waiting on players or holding back for peers
+    pub fn is_waiting(&self) -> bool {
+        matches!(self, Progress::WaitingOnPlayers { .. } | Progress::HoldingBack { .. })
+    }
+
+    pub fn rollback(&self) -> Option<&RollbackReport<Id>> {
+        match self {
+            Progress::Advanced { rollback, .. } => rollback.as_ref(),
+            Progress::WaitingOnPlayers { .. } | Progress::HoldingBack { .. } => None
+        }
+    }
+
+    pub fn into_rollback(self) -> Option<RollbackReport<Id>> {
+        match self {
+            Progress::Advanced { rollback, .. } => rollback,
+            Progress::WaitingOnPlayers { .. } | Progress::HoldingBack { .. } => None
+        }
+    }
+
+    pub fn confirmed_frames(&self) -> Range<usize> {
+        match self {
+            Progress::Advanced { confirmed_frames, .. } => confirmed_frames.clone(),
+            Progress::WaitingOnPlayers { .. } | Progress::HoldingBack { .. } => 0..0
+        }
+    }
+
+    pub fn desync(&self) -> Option<&Desync> {
+        match self {
+            Progress::Advanced { desync, .. } => desync.as_ref(),
+            Progress::WaitingOnPlayers { .. } | Progress::HoldingBack { .. } => None
+        }
+    }
+}
+
+// Checksums of a confirmed frame which differed between this peer and a remote one
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Desync {
+    pub frame: usize,
+    pub local: u64,
+    pub remote: u64
+}
+
+// Describes a rollback caused by inputs which differed from their predictions
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RollbackReport<Id = DefaultPlayerId> {
+    // Earliest frame whose predicted inputs turned out to be wrong
+    pub earliest_mispredicted_frame: usize,
+    // Number of previously simulated frames which were simulated again
+    pub frames_resimulated: usize,
+    // Players whose late inputs differed from the prediction, sorted by id
+    pub players: Vec<Id>
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "Input: Serialize, State: Serialize, Predictor: Serialize, Id: Serialize",
+    deserialize = "Input: Deserialize<'de>, State: Deserialize<'de>, Predictor: Deserialize<'de>, Id: Deserialize<'de>"
+)))]
+pub struct RollbackStateManager<Input, State, Predictor = RepeatLastInput, Id = DefaultPlayerId>
+        where Input: Eq + Clone + Debug, State: SaveState + Debug, Predictor: InputPredictor<Input, Id>, Id: PlayerId {
     pub max_history: usize,
     pub oldest_frame_index: usize,
     pub current_frame_index: usize,
     pub newest_frame_index: usize,
     pub stored_state: State,
     pub current_frame_state: State,
-    pub recorded_inputs: HashMap<usize, HashMap<Uuid, Input>>
+    // Inputs received from each player within the rollback window
+    pub player_inputs: BTreeMap<Id, PlayerInputs<Input>>,
+    // Number of frames between saved states. Larger intervals use less memory at the cost of
+    // re-simulating up to this many extra frames on rollback
+    pub snapshot_interval: usize,
+    // States after simulating each frame which is a multiple of the snapshot interval
+    pub snapshots: SnapshotBuffer<State>,
+    // Earliest frame which hasn't been simulated with the current known inputs
+    pub first_unsimulated_frame: usize,
+    // Earliest frame and players with mispredicted inputs since the last progressed frame
+    pub earliest_misprediction: Option<usize>,
+    pub mispredicted_players: Vec<Id>,
+    // First frame which hasn't been reported as confirmed by progressing
+    pub next_confirmed_frame: usize,
+    // Guesses inputs for frames which haven't been received yet
+    pub predictor: Predictor,
+    // Newest frame up to which every input from each player has been received
+    pub confirmed_frames: HashMap<Id, usize>,
+    // Frames received from each player past a gap after their confirmed frame
+    pub unconfirmed_inputs: HashMap<Id, BTreeSet<usize>>,
+    // Players added or removed explicitly. Players who only ever send inputs are treated as
+    // present on every frame
+    pub registered_players: HashSet<Id>,
+    // Joins, leaves and disconnects of each registered player by frame
+    pub player_events: HashMap<Id, BTreeMap<usize, PlayerEvent>>,
+    // When set, frames older than the rollback window must be confirmed by every player before
+    // the game can progress past them
+    pub wait_for_confirmation: bool,
+    // Checksums of simulated frames, recorded once checksums are enabled or by games with their own
+    // checksum. Remote checksums wait
+    // here until the local frame is confirmed and can be compared
+    // Not serialized, so checksums need to be enabled again after deserializing
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub checksum_state: Option<fn(&State) -> u64>,
+    pub frame_checksums: HashMap<usize, u64>,
+    pub remote_checksums: HashMap<usize, u64>,
+    // Inputs of every frame which has left the rollback window since recording started
+    pub replay: Option<Replay<Input, State, Id>>,
+    // Inputs each frame in the rollback window was last simulated with, kept while recording a
+    // replay so it reproduces the simulated states even when predictions have since changed
+    pub replay_inputs: BTreeMap<usize, FrameInputs<Input, Id>>
+}
+
+impl<Input: Eq + Clone + Debug, State: SaveState + Debug, Id: PlayerId> RollbackStateManager<Input, State, RepeatLastInput, Id> {
+    pub fn new(initial_state: State, max_rollback: usize) -> RollbackStateManager<Input, State, RepeatLastInput, Id> {
+        RollbackStateManager::with_snapshot_interval(initial_state, max_rollback, 1)
+    }
+
+    pub fn with_snapshot_interval(initial_state: State, max_rollback: usize, snapshot_interval: usize) -> RollbackStateManager<Input, State, RepeatLastInput, Id> {
+        RollbackStateManager::with_predictor(initial_state, max_rollback, snapshot_interval, RepeatLastInput)
+    }
 }
 
-impl<Input: Eq + Clone + Debug, State: Clone + Debug> RollbackStateManager<Input, State> {
-    pub fn new(initial_state: State, max_rollback: usize) -> RollbackStateManager<Input, State> {
+impl<Input, State, Predictor, Id> RollbackStateManager<Input, State, Predictor, Id>
+        where Input: Eq + Clone + Debug, State: SaveState + Debug, Predictor: InputPredictor<Input, Id>, Id: PlayerId {
+    pub fn with_predictor(initial_state: State, max_rollback: usize, snapshot_interval: usize, predictor: Predictor) -> RollbackStateManager<Input, State, Predictor, Id> {
+        let snapshot_interval = snapshot_interval.max(1);
+        // Snapshots span the rollback window plus up to one interval the stored state lags behind
+        let snapshot_capacity = max_rollback / snapshot_interval + 3;
+
         RollbackStateManager {
             max_history: max_rollback,
             oldest_frame_index: 0,
             current_frame_index: 0,
             newest_frame_index: 0,
-            stored_state: initial_state.clone(),
+            stored_state: initial_state.save_state(),
             current_frame_state: initial_state,
-            recorded_inputs: HashMap::new()
+            player_inputs: BTreeMap::new(),
+            snapshot_interval,
+            snapshots: SnapshotBuffer::new(snapshot_capacity),
+            first_unsimulated_frame: 0,
+            earliest_misprediction: None,
+            mispredicted_players: Vec::new(),
+            next_confirmed_frame: 0,
+            predictor,
+            confirmed_frames: HashMap::new(),
+            unconfirmed_inputs: HashMap::new(),
+            registered_players: HashSet::new(),
+            player_events: HashMap::new(),
+            wait_for_confirmation: false,
+            checksum_state: None,
+            frame_checksums: HashMap::new(),
+            remote_checksums: HashMap::new(),
+            replay: None,
+            replay_inputs: BTreeMap::new()
+        }
+    }
+
+    // Received or predicted inputs of every player on the given frame. The inputs are ordered by
+    // player id so every peer iterates them the same way, and each is marked with its status
+    pub fn get_frame_inputs(&self, index: usize) -> FrameInputs<Input, Id> {
+        // Players who joined but haven't sent an input yet only have predictions
+        let unsent_players = self.registered_players.iter()
+            .filter(|id| !self.player_inputs.contains_key(*id));
+
+        self.player_inputs.keys()
+            .chain(unsent_players)
+            .filter_map(|id| self.player_input(index, id).map(|(input, status)| (id.clone(), input, status)))
+            .collect()
+    }
+
+    fn player_input(&self, index: usize, id: &Id) -> Option<(Input, InputStatus)> {
+        if !self.is_player_active(index, id) {
+            return None;
+        }
+
+        let inputs = self.player_inputs.get(id);
+        let (input, status) = match inputs.and_then(|inputs| inputs.get(index)) {
+            Some(input) if inputs.is_some_and(|inputs| inputs.is_predicted(index)) => (input.clone(), InputStatus::Predicted),
+            Some(input) => (input.clone(), InputStatus::Confirmed),
+            None => (self.predict_input(index, id)?, InputStatus::Predicted)
+        };
+        if self.is_player_connected(index, id) {
+            Some((input, status))
+        } else {
+            Some((input, InputStatus::Disconnected))
+        }
+    }
+
+    // Newest join, leave or disconnect of a registered player on or before the given frame
+    fn player_event(&self, index: usize, id: &Id) -> Option<PlayerEvent> {
+        self.player_events.get(id)
+            .and_then(|events| events.range(..=index).next_back())
+            .map(|(_, event)| *event)
+    }
+
+    // Whether a player is part of the game on the given frame. Disconnected players are still
+    // part of the game
+    pub fn is_player_active(&self, index: usize, id: &Id) -> bool {
+        !self.registered_players.contains(id) || matches!(self.player_event(index, id), Some(PlayerEvent::Joined) | Some(PlayerEvent::Disconnected))
+    }
+
+    // Whether a player is part of the game and still sending inputs on the given frame
+    pub fn is_player_connected(&self, index: usize, id: &Id) -> bool {
+        !self.registered_players.contains(id) || self.player_event(index, id) == Some(PlayerEvent::Joined)
+    }
+
+    // Newest frame for which every player's input has been received. Frames up to this one will
+    // never be rolled back
+    pub fn confirmed_frame(&self) -> Option<usize> {
+        self.confirming_players()
+            .filter_map(|id| self.first_unconfirmed_frame(id))
+            .min()?
+            .checked_sub(1)
+    }
+
+    fn confirming_players(&self) -> impl Iterator<Item = &Id> + '_ {
+        let mut players: HashSet<&Id> = self.confirmed_frames.keys().collect();
+        players.extend(self.registered_players.iter());
+        players.extend(self.player_inputs.keys());
+        players.into_iter()
+    }
+
+    // First frame whose input from the player is still needed, if any. Players who have left or
+    // disconnected hold back confirmation only for frames before that, until they join again
+    fn first_unconfirmed_frame(&self, id: &Id) -> Option<usize> {
+        let mut frame = self.next_expected_frame(id);
+        while !self.is_player_connected(frame, id) {
+            frame = self.player_events.get(id)?
+                .range(frame + 1..)
+                .find(|(_, event)| **event == PlayerEvent::Joined)
+                .map(|(join_frame, _)| *join_frame)?;
+        }
+        Some(frame)
+    }
+
+    // Players who haven't confirmed every frame before the given frame, sorted by id
+    pub fn players_waiting_for(&self, frame: usize) -> Vec<Id> {
+        let mut players: Vec<Id> = self.confirming_players()
+            .filter(|id| self.first_unconfirmed_frame(id).is_some_and(|first_unconfirmed_frame| first_unconfirmed_frame < frame))
+            .cloned()
+            .collect();
+        players.sort();
+        players
+    }
+
+    // Recorded or predicted input for a single player
+    pub fn get_player_input(&self, index: usize, id: &Id) -> Option<Input> {
+        self.player_input(index, id).map(|(input, _)| input)
+    }
+
+    fn predict_input(&self, index: usize, id: &Id) -> Option<Input> {
+        let history = InputHistory::new(id.clone(), index, self.player_inputs.get(id));
+        if history.last().is_some() || self.registered_players.contains(id) {
+            self.predictor.predict(&history)
+        } else {
+            None
+        }
+    }
+
+    // Find the newest valid state to resume simulation from. Returns the first frame to simulate
+    // and the state before that frame
+    fn restore_state(&mut self) -> (usize, State) {
+        self.snapshots.discard_from(self.first_unsimulated_frame);
+        match self.snapshots.latest_before(self.first_unsimulated_frame) {
+            Some((snapshot_frame, state)) => (snapshot_frame + 1, state.save_state()),
+            None => (self.oldest_frame_index, self.stored_state.save_state())
+        }
+    }
+
+    // Simulate forward from the state before the first frame to the current frame, saving
+    // snapshots along the way
+    fn simulate_frames<F>(&mut self, first_frame: usize, mut state: State, update: &F)
+            where F: Fn(&FrameInputs<Input, Id>, State) -> State {
+        for frame in first_frame..self.current_frame_index + 1 {
+            let inputs = self.get_frame_inputs(frame);
+            state = update(&inputs, state);
+            if let Some(checksum_state) = self.checksum_state {
+                self.frame_checksums.insert(frame, checksum_state(&state));
+            }
+            if frame % self.snapshot_interval == 0 {
+                self.snapshots.push(frame, state.save_state());
+            }
+            if self.replay.is_some() {
+                self.replay_inputs.insert(frame, inputs);
+            }
+        }
+        self.current_frame_state = state;
+        self.first_unsimulated_frame = self.current_frame_index + 1;
+    }
+
+    // Throw away the simulated states from the given frame onward and simulate them again without
+    // progressing. Returns the first frame simulated, which is earlier than requested when the
+    // nearest saved state is
+    pub fn resimulate_from<F>(&mut self, frame: usize, update: F) -> usize
+            where F: Fn(&FrameInputs<Input, Id>, State) -> State {
+        self.first_unsimulated_frame = self.first_unsimulated_frame.min(frame.max(self.oldest_frame_index));
+        if self.first_unsimulated_frame > self.current_frame_index {
+            return self.first_unsimulated_frame;
+        }
+
+        let (first_frame, state) = self.restore_state();
+        self.simulate_frames(first_frame, state, &update);
+        first_frame
+    }
+
+    // Same as resimulate_from, but the update function changes the state in place
+    pub fn resimulate_from_mut<F>(&mut self, frame: usize, update: F) -> usize
+            where F: FnMut(&FrameInputs<Input, Id>, &mut State) {
+        self.resimulate_in_place(frame, &mut InPlaceUpdate(update))
+    }
+
+    // Same as resimulate_from, but simulated by a game
+    pub fn resimulate_game<Game>(&mut self, frame: usize, game: &mut Game) -> usize
+            where Game: RollbackGame<Input = Input, State = State, Id = Id> {
+        self.resimulate_in_place(frame, &mut GameUpdate(game))
+    }
+
+    fn resimulate_in_place<S: FrameSimulation<Input, State, Id>>(&mut self, frame: usize, simulation: &mut S) -> usize {
+        self.first_unsimulated_frame = self.first_unsimulated_frame.min(frame.max(self.oldest_frame_index));
+        if self.first_unsimulated_frame > self.current_frame_index {
+            return self.first_unsimulated_frame;
+        }
+
+        let first_frame = self.load_restored_state(simulation);
+        self.simulate_frames_mut(first_frame, simulation);
+        first_frame
+    }
+
+    // Load the newest valid state to resume simulation from into the current state, in place.
+    // Returns the first frame to simulate
+    fn load_restored_state<S: FrameSimulation<Input, State, Id>>(&mut self, simulation: &mut S) -> usize {
+        self.snapshots.discard_from(self.first_unsimulated_frame);
+        match self.snapshots.latest_before(self.first_unsimulated_frame) {
+            Some((snapshot_frame, state)) => {
+                simulation.load(&mut self.current_frame_state, state);
+                snapshot_frame + 1
+            },
+            None => {
+                simulation.load(&mut self.current_frame_state, &self.stored_state);
+                self.oldest_frame_index
+            }
+        }
+    }
+
+    // Same as simulate_frames, but updating the current state in place
+    fn simulate_frames_mut<S: FrameSimulation<Input, State, Id>>(&mut self, first_frame: usize, simulation: &mut S) {
+        for frame in first_frame..self.current_frame_index + 1 {
+            let inputs = self.get_frame_inputs(frame);
+            simulation.advance(&inputs, &mut self.current_frame_state);
+            let checksum = simulation.checksum(&self.current_frame_state)
+                .or_else(|| self.checksum_state.map(|checksum_state| checksum_state(&self.current_frame_state)));
+            if let Some(checksum) = checksum {
+                self.frame_checksums.insert(frame, checksum);
+            }
+            if frame % self.snapshot_interval == 0 {
+                self.snapshots.push(frame, simulation.save(&self.current_frame_state));
+            }
+            if self.replay.is_some() {
+                self.replay_inputs.insert(frame, inputs);
+            }
+        }
+        self.first_unsimulated_frame = self.current_frame_index + 1;
+    }
+
+    // Progressing would push unconfirmed frames out of the rollback window
+    fn check_confirmation(&self) -> Option<Progress<Id>> {
+        if self.wait_for_confirmation {
+            let next_frame_index = self.current_frame_index + 1;
+            let players = self.players_waiting_for(next_frame_index.saturating_sub(self.max_history));
+            if !players.is_empty() {
+                return Some(Progress::WaitingOnPlayers {
+                    frame: next_frame_index,
+                    players
+                })
+            }
+        }
+        None
+    }
+
+    // Progress the frame counter by 1 and compute the state of that frame under current known
+    // inputs. Reports any mispredicted inputs which caused previous frames to be simulated again,
+    // or the players being waited on when waiting for confirmation
+    pub fn progress_frame<F>(&mut self, update: F) -> Result<Progress<Id>>
+            where F: Fn(&FrameInputs<Input, Id>, State) -> State {
+        if let Some(waiting) = self.check_confirmation() {
+            return Ok(waiting);
+        }
+        let previous_frame_index = self.current_frame_index;
+        self.current_frame_index += 1;
+
+        // Resume from the newest state which is still valid and simulate forward to the current
+        // frame
+        let (first_frame, state) = if self.first_unsimulated_frame == self.current_frame_index {
+            // Nothing was invalidated, so continue from the previous frame
+            (self.current_frame_index, self.current_frame_state.save_state())
+        } else {
+            self.restore_state()
+        };
+        self.simulate_frames(first_frame, state, &update);
+
+        self.finish_progress(previous_frame_index, first_frame)
+    }
+
+    // Same as progress_frame, but the update function changes the state in place. States are only
+    // copied when saving snapshots or rolling back, through SaveState
+    pub fn progress_frame_mut<F>(&mut self, update: F) -> Result<Progress<Id>>
+            where F: FnMut(&FrameInputs<Input, Id>, &mut State) {
+        self.progress_in_place(&mut InPlaceUpdate(update))
+    }
+
+    // Progress a frame by advancing a game, which also saves and loads the states rolled back
+    pub fn progress_game<Game>(&mut self, game: &mut Game) -> Result<Progress<Id>>
+            where Game: RollbackGame<Input = Input, State = State, Id = Id> {
+        self.progress_in_place(&mut GameUpdate(game))
+    }
+
+    fn progress_in_place<S: FrameSimulation<Input, State, Id>>(&mut self, simulation: &mut S) -> Result<Progress<Id>> {
+        if let Some(waiting) = self.check_confirmation() {
+            return Ok(waiting);
         }
+        let previous_frame_index = self.current_frame_index;
+        self.current_frame_index += 1;
+
+        // Without a rollback the current state is already the state before the new frame
+        let first_frame = if self.first_unsimulated_frame == self.current_frame_index {
+            self.current_frame_index
+        } else {
+            self.load_restored_state(simulation)
+        };
+        self.simulate_frames_mut(first_frame, simulation);
+
+        self.finish_progress(previous_frame_index, first_frame)
     }
 
-    // Builds inputs up by looping backwards searching for inputs for each id
-    pub fn get_frame_inputs(&self, index: usize) -> HashMap<Uuid, Input> {
-        let mut inputs = HashMap::new();
+    // Report rollbacks and slide the rollback window forward after simulating a progressed frame
+    fn finish_progress(&mut self, previous_frame_index: usize, first_frame: usize) -> Result<Progress<Id>> {
+        let rollback = self.earliest_misprediction.take().map(|earliest_mispredicted_frame| {
+            let mut players = std::mem::take(&mut self.mispredicted_players);
+            players.sort();
+            players.dedup();
+
+            RollbackReport {
+                earliest_mispredicted_frame,
+                frames_resimulated: (previous_frame_index + 1).saturating_sub(first_frame),
+                players
+            }
+        });
+
+        // Every frame in the window has just been simulated, so confirmed ones are final
+        let first_confirmed_frame = self.next_confirmed_frame.max(self.oldest_frame_index);
+        let confirmed_end = self.confirmed_frame()
+            .map_or(0, |confirmed_frame| confirmed_frame + 1)
+            .min(self.first_unsimulated_frame)
+            .max(first_confirmed_frame);
+        self.next_confirmed_frame = confirmed_end;
+
+        // Compute oldest possible frame
+        let max_oldest_frame = self.current_frame_index.saturating_sub(self.max_history);
+        // If the currently recorded oldest frame is older than the oldest possible frame, move the
+        // stored state forward to the newest snapshot before the oldest possible frame
+        while let Some(snapshot_frame) = self.snapshots.oldest_frame() {
+            let new_oldest_frame = snapshot_frame + 1;
+            if new_oldest_frame > max_oldest_frame {
+                break;
+            }
+
+            if new_oldest_frame > self.oldest_frame_index {
+                if self.replay.is_some() {
+                    let remaining_inputs = self.replay_inputs.split_off(&new_oldest_frame);
+                    let mut simulated_inputs = std::mem::replace(&mut self.replay_inputs, remaining_inputs);
+                    let final_inputs: Vec<FrameInputs<Input, Id>> = (self.oldest_frame_index..new_oldest_frame)
+                        .map(|frame| simulated_inputs.remove(&frame).unwrap_or_else(|| self.get_frame_inputs(frame)))
+                        .collect();
+                    if let Some(replay) = self.replay.as_mut() {
+                        replay.frame_inputs.extend(final_inputs);
+                    }
+                }
+
+                // The inputs of the new oldest frame stand in for every forgotten input, which keeps
+                // the newest input before every frame the same. Predictions which look further back
+                // can still change, so those frames are simulated again
+                let predictions = if self.predictor.uses_only_last_input() {
+                    Vec::new()
+                } else {
+                    self.simulated_predictions(new_oldest_frame + 1)
+                };
+                let oldest_inputs = self.get_frame_inputs(new_oldest_frame);
+                for inputs in self.player_inputs.values_mut() {
+                    inputs.advance(new_oldest_frame);
+                }
+                let input_capacity = self.input_capacity();
+                for (id, input) in oldest_inputs {
+                    self.player_inputs.entry(id)
+                        .or_insert_with(|| PlayerInputs::new(new_oldest_frame, input_capacity))
+                        .insert_predicted(new_oldest_frame, input);
+                }
+                self.player_inputs.retain(|_, inputs| !inputs.is_empty());
+                for (id, frame, prediction) in predictions {
+                    if self.get_player_input(frame, &id) != prediction {
+                        self.record_misprediction(frame, id);
+                    }
+                }
 
-        for previous_index in (self.oldest_frame_index..index + 1).rev() {
-            if let Some(current_frame_inputs) = self.recorded_inputs.get(&previous_index) {
-                for (id, input) in current_frame_inputs.iter() {
-                    if !inputs.contains_key(id) {
-                        inputs.insert(id.clone(), input.clone());
+                // Likewise each player's newest event stands in for their forgotten events
+                for events in self.player_events.values_mut() {
+                    let newest_event = events.range(..=new_oldest_frame).next_back().map(|(_, event)| *event);
+                    *events = events.split_off(&(new_oldest_frame + 1));
+                    if let Some(event) = newest_event.filter(|event| *event != PlayerEvent::Left) {
+                        events.insert(new_oldest_frame, event);
                     }
                 }
+                self.oldest_frame_index = new_oldest_frame;
+
+                // Frames which left the window can't change anymore, so inputs which never arrived
+                // for them no longer hold back confirmation
+                let final_frame = new_oldest_frame - 1;
+                let players: Vec<Id> = self.confirmed_frames.keys()
+                    .chain(self.registered_players.iter())
+                    .chain(self.player_inputs.keys())
+                    .filter(|id| self.confirmed_frames.get(*id).is_none_or(|confirmed_frame| *confirmed_frame < final_frame))
+                    .cloned()
+                    .collect();
+                for id in players {
+                    self.confirm_through(&id, final_frame);
+                }
+            }
+            if let Some((_, state)) = self.snapshots.pop_oldest() {
+                self.stored_state = state;
             }
         }
-        inputs
+
+        // Keep checksums for a window of confirmed frames behind the rollback window so that slower
+        // peers can still be compared against
+        let oldest_checksum_frame = self.oldest_frame_index.saturating_sub(self.max_history);
+        self.frame_checksums.retain(|frame, _| *frame >= oldest_checksum_frame);
+        self.remote_checksums.retain(|frame, _| *frame >= oldest_checksum_frame);
+        let desync = self.compare_remote_checksums();
+
+        Ok(Progress::Advanced {
+            rollback,
+            confirmed_frames: first_confirmed_frame..confirmed_end,
+            desync
+        })
     }
- 
-    // Show the current frame state
-    fn compute_frame_state<F>(&self, index: usize, update: F) -> State 
-            where F: Fn(&HashMap<Uuid, Input>, State) -> State {
-        // Clone the stored frame and update it until the current frame
-        let mut state = self.stored_state.clone();
-        for i in self.oldest_frame_index .. index + 1 {
-            state = update(&self.get_frame_inputs(i), state);
+
+    // Checksum of a frame which has been simulated with every player's real inputs
+    pub fn confirmed_checksum(&self, frame: usize) -> Option<u64> {
+        let confirmed = self.confirmed_frame().is_some_and(|confirmed_frame| frame <= confirmed_frame);
+        if confirmed && frame < self.first_unsimulated_frame {
+            self.frame_checksums.get(&frame).copied()
+        } else {
+            None
         }
-        state
     }
 
-    // Progress the frame counter by 1 and return the state of that frame under current known
-    // inputs
-    pub fn progress_frame<F>(&mut self, update: F) where F: Fn(&HashMap<Uuid, Input>, State) -> State {
-        // Increment current frame
-        self.current_frame_index = self.current_frame_index + 1;
-        // Compute oldest possible frame
-        let max_oldest_frame = self.current_frame_index.checked_sub(self.max_history).unwrap_or(0);
-        // If the currently recorded oldest frame is older than the oldest possible frame, update
-        // the stored state until the oldest recorded frame matches the oldest possible frame
-        if self.oldest_frame_index < max_oldest_frame {
-            let mut state = self.stored_state.clone();
-            for i in 0..max_oldest_frame - self.oldest_frame_index {
-                let frame = self.oldest_frame_index + i;
-                state = update(&self.get_frame_inputs(frame), state);
+    // Compare a checksum computed by a peer against the local checksum for the same frame. If the
+    // local frame isn't confirmed yet, the comparison happens in a later progress_frame which
+    // reports any desync in its progress
+    pub fn handle_remote_checksum(&mut self, frame: usize, remote: u64) -> Result<()> {
+        self.remote_checksums.insert(frame, remote);
+        match self.compare_remote_checksums() {
+            Some(Desync { frame, local, remote }) => Err(RollbackError::Desync { frame, local, remote }),
+            None => Ok(())
+        }
+    }
+
+    fn compare_remote_checksums(&mut self) -> Option<Desync> {
+        let mut comparable_frames: Vec<usize> = self.remote_checksums.keys()
+            .copied()
+            .filter(|frame| self.confirmed_checksum(*frame).is_some())
+            .collect();
+        comparable_frames.sort();
+
+        for frame in comparable_frames {
+            if let (Some(local), Some(remote)) = (self.confirmed_checksum(frame), self.remote_checksums.remove(&frame)) {
+                if local != remote {
+                    return Some(Desync { frame, local, remote });
+                }
             }
+        }
+        None
+    }
+
+    // Newest saved state which only depends on confirmed inputs, as the first frame after it and
+    // the state before that frame. Spectators can join from here
+    pub fn confirmed_snapshot(&self) -> (usize, State) {
+        let confirmed_end = self.confirmed_frame().map_or(0, |confirmed_frame| confirmed_frame + 1);
+        match self.snapshots.latest_before(confirmed_end.min(self.first_unsimulated_frame)) {
+            Some((snapshot_frame, state)) => (snapshot_frame + 1, state.save_state()),
+            None => (self.oldest_frame_index, self.stored_state.save_state())
+        }
+    }
+
+    // Start recording the inputs of every frame from the oldest frame in the rollback window
+    pub fn record_replay(&mut self) {
+        self.replay = Some(Replay::new(self.oldest_frame_index, self.stored_state.save_state()));
+        self.replay_inputs.clear();
+    }
+
+    // Replay of every recorded frame up to the current frame. Frames still in the rollback window
+    // use the inputs they were last simulated with
+    pub fn replay(&self) -> Option<Replay<Input, State, Id>> {
+        self.replay.as_ref().map(|replay| {
+            let mut replay = replay.save_replay();
+            replay.frame_inputs.extend(self.simulated_frames_from(self.oldest_frame_index).map(|frame| {
+                self.replay_inputs.get(&frame).cloned().unwrap_or_else(|| self.get_frame_inputs(frame))
+            }));
+            replay
+        })
+    }
 
-            self.recorded_inputs.insert(max_oldest_frame, self.get_frame_inputs(max_oldest_frame));
-            self.oldest_frame_index = max_oldest_frame;
-            self.stored_state = state;
+    // Frames from the given frame up to the current frame which have already been simulated
+    fn simulated_frames_from(&self, frame: usize) -> Range<usize> {
+        if self.current_frame_index > 0 {
+            frame..self.current_frame_index + 1
+        } else {
+            frame..frame
         }
+    }
+
+    // Stop recording checksums enabled on the state manager and forget any pending comparisons
+    pub fn disable_checksums(&mut self) {
+        self.checksum_state = None;
+        self.frame_checksums.clear();
+        self.remote_checksums.clear();
+    }
+
+    // Frames of inputs held in each player's ring buffer. The rollback window can lag up to a
+    // snapshot interval behind, and inputs may arrive as far ahead again
+    fn input_capacity(&self) -> usize {
+        2 * (self.max_history + self.snapshot_interval)
+    }
+
+    // Predicted inputs of every player on the frames from the given frame onward which have been
+    // simulated
+    fn simulated_predictions(&self, frame: usize) -> Vec<(Id, usize, Option<Input>)> {
+        let simulated_frames = frame..self.first_unsimulated_frame.min(self.current_frame_index + 1);
+        let unsent_players = self.registered_players.iter()
+            .filter(|id| !self.player_inputs.contains_key(*id));
 
-        // Update the stored frame till the current frame index and return
-        self.current_frame_state = self.compute_frame_state(self.current_frame_index, update);
+        self.player_inputs.keys()
+            .chain(unsent_players)
+            .flat_map(|id| simulated_frames.clone()
+                .filter(move |simulated_frame| self.player_inputs.get(id).is_none_or(|inputs| inputs.get(*simulated_frame).is_none()))
+                .map(move |simulated_frame| (id.clone(), simulated_frame, self.get_player_input(simulated_frame, id))))
+            .collect()
+    }
+
+    fn record_misprediction(&mut self, frame: usize, id: Id) {
+        self.earliest_misprediction = Some(self.earliest_misprediction.map_or(frame, |earliest| earliest.min(frame)));
+        self.mispredicted_players.push(id);
+
+        // Any states computed for this frame or later were built on stale inputs
+        self.first_unsimulated_frame = self.first_unsimulated_frame.min(frame);
+    }
+
+    // Apply a change to a player's inputs or presence and invalidate any simulated frames whose
+    // inputs for that player changed as a result
+    fn change_player<F>(&mut self, frame: usize, id: &Id, change: F) where F: FnOnce(&mut Self) {
+        // Predictions for every simulated frame from this one onward may depend on the change.
+        // Disconnecting changes the input status the game sees, so it counts as a change too
+        let simulated_frames = self.simulated_frames_from(frame);
+        let previous_inputs: Vec<(Option<Input>, bool)> = simulated_frames.clone()
+            .map(|simulated_frame| (self.get_player_input(simulated_frame, id), self.is_player_connected(simulated_frame, id)))
+            .collect();
+
+        change(self);
+
+        // Changes matching the predictions confirm the simulated frames instead of invalidating
+        // them
+        let mispredicted_frame = simulated_frames.zip(previous_inputs)
+            .find(|(simulated_frame, previous_input)| (self.get_player_input(*simulated_frame, id), self.is_player_connected(*simulated_frame, id)) != *previous_input)
+            .map(|(simulated_frame, _)| simulated_frame);
+        if let Some(mispredicted_frame) = mispredicted_frame {
+            self.record_misprediction(mispredicted_frame, id.clone());
+        }
     }
 
     // Store input or a given player id
-    pub fn handle_input(&mut self, frame: usize, id: Uuid, input: Input) -> Result<()> {
+    pub fn handle_input(&mut self, frame: usize, id: Id, input: Input) -> Result<()> {
         if frame < self.oldest_frame_index {
             return Err(RollbackError::InputTooOld {
                 input_frame: frame,
@@ -115,29 +839,119 @@ impl<Input: Eq + Clone + Debug, State: Clone + Debug> RollbackStateManager<Input
             })
         }
 
-        let recorded_inputs = self.recorded_inputs.entry(frame).or_insert(HashMap::new());
-        recorded_inputs.insert(id, input);
+        self.change_player(frame, &id, |manager| {
+            let (oldest_frame_index, input_capacity) = (manager.oldest_frame_index, manager.input_capacity());
+            manager.player_inputs.entry(id.clone())
+                .or_insert_with(|| PlayerInputs::new(oldest_frame_index, input_capacity))
+                .insert(frame, input);
+
+            let next_frame = manager.next_expected_frame(&id);
+            if frame == next_frame {
+                manager.confirm_through(&id, frame);
+            } else if frame > next_frame {
+                manager.unconfirmed_inputs.entry(id.clone()).or_default().insert(frame);
+            }
+        });
+        Ok(())
+    }
+
+    // First frame whose input hasn't arrived from a player. Inputs may arrive out of order, so
+    // players who haven't confirmed anything yet are expected from the oldest frame in the window
+    // unless they were added later
+    pub fn next_expected_frame(&self, id: &Id) -> usize {
+        self.confirmed_frames.get(id).map_or(self.oldest_frame_index, |confirmed_frame| confirmed_frame + 1)
+    }
+
+    // Record that a player's inputs are known up to the given frame, along with any frames received
+    // after it without a gap
+    fn confirm_through(&mut self, id: &Id, frame: usize) {
+        let mut confirmed_frame = self.confirmed_frames.get(id).map_or(frame, |confirmed_frame| frame.max(*confirmed_frame));
+        if let Some(unconfirmed_inputs) = self.unconfirmed_inputs.get_mut(id) {
+            *unconfirmed_inputs = unconfirmed_inputs.split_off(&(confirmed_frame + 1));
+            while unconfirmed_inputs.remove(&(confirmed_frame + 1)) {
+                confirmed_frame += 1;
+            }
+            if unconfirmed_inputs.is_empty() {
+                self.unconfirmed_inputs.remove(id);
+            }
+        }
+        self.confirmed_frames.insert(id.clone(), confirmed_frame);
+    }
+
+    fn handle_player_event(&mut self, frame: usize, id: Id, event: PlayerEvent) -> Result<()> {
+        if frame < self.oldest_frame_index {
+            return Err(RollbackError::PlayerEventTooOld {
+                event_frame: frame,
+                oldest_valid_frame: self.oldest_frame_index
+            })
+        }
+
+        self.change_player(frame, &id, |manager| {
+            // Players who were already sending inputs stay present from their first input
+            let first_input_frame = manager.player_inputs.get(&id).and_then(|inputs| inputs.oldest_input_frame());
+            if manager.registered_players.insert(id.clone()) {
+                if let Some(first_input_frame) = first_input_frame.filter(|first_input_frame| *first_input_frame < frame) {
+                    manager.player_events.entry(id.clone()).or_default().insert(first_input_frame, PlayerEvent::Joined);
+                }
+            }
+
+            manager.player_events.entry(id.clone()).or_default().insert(frame, event);
+        });
         Ok(())
     }
+
+    // Add a player to the game from the given frame onward
+    pub fn add_player(&mut self, id: Id, frame: usize) -> Result<()> {
+        self.handle_player_event(frame, id.clone(), PlayerEvent::Joined)?;
+
+        // Inputs before joining are never needed, so they can't hold back confirmation
+        if let Some(previous_frame) = frame.checked_sub(1) {
+            self.confirm_through(&id, previous_frame);
+        }
+        Ok(())
+    }
+
+    // Remove a player from the game from the given frame onward
+    pub fn remove_player(&mut self, id: Id, frame: usize) -> Result<()> {
+        self.handle_player_event(frame, id, PlayerEvent::Left)
+    }
+
+    // Keep a player in the game from the given frame onward with predicted inputs marked as
+    // disconnected, without waiting for their inputs to confirm frames. Adding the player again
+    // reconnects them
+    pub fn disconnect_player(&mut self, id: Id, frame: usize) -> Result<()> {
+        self.handle_player_event(frame, id, PlayerEvent::Disconnected)
+    }
+}
+
+impl<Input, State, Predictor, Id> RollbackStateManager<Input, State, Predictor, Id>
+        where Input: Eq + Clone + Debug, State: SaveState + Debug + Checksum, Predictor: InputPredictor<Input, Id>, Id: PlayerId {
+    // Record a checksum of every simulated frame so that confirmed frames can be compared with
+    // other peers
+    pub fn enable_checksums(&mut self) {
+        self.checksum_state = Some(State::checksum);
+    }
 }
 
 #[cfg(test)]
+#[allow(non_snake_case)]
 mod tests {
     use super::*;
 
+    use std::cell::Cell;
+
     type Input = u64;
     type State = u64;
+    type Id = u32;
 
-    lazy_static! {
-        static ref P1ID: Uuid = Uuid::new_v4();
-        static ref P2ID: Uuid = Uuid::new_v4();
-    }
-    
-    fn update(input: &HashMap<Uuid, Input>, state: State) -> State {
+    const P1ID: Id = 1;
+    const P2ID: Id = 2;
+
+    fn update(input: &FrameInputs<Input, Id>, state: State) -> State {
         let mut current_state = state;
 
         for input_value in input.values() {
-            current_state = current_state + input_value;
+            current_state += input_value;
         }
 
         current_state
@@ -147,22 +961,49 @@ mod tests {
     fn HandleInput_GetFrameInput_MultipleFrames_BuildsInputs() -> Result<()> {
         let mut rollback_manager = RollbackStateManager::new(0, 4);
 
-        rollback_manager.handle_input(0, P1ID.clone(), 1)?;
-        rollback_manager.handle_input(1, P2ID.clone(), 2)?;
-        rollback_manager.handle_input(2, P1ID.clone(), 0)?;
-        rollback_manager.handle_input(2, P2ID.clone(), 0)?;
+        rollback_manager.handle_input(0, P1ID, 1)?;
+        rollback_manager.handle_input(1, P2ID, 2)?;
+        rollback_manager.handle_input(2, P1ID, 0)?;
+        rollback_manager.handle_input(2, P2ID, 0)?;
 
         let frame_0_inputs = rollback_manager.get_frame_inputs(0);
-        assert_eq!(frame_0_inputs.get(&P1ID.clone()), Some(&1));
-        assert_eq!(frame_0_inputs.get(&P2ID.clone()), None);
+        assert_eq!(frame_0_inputs.get(&P1ID), Some(&1));
+        assert_eq!(frame_0_inputs.get(&P2ID), None);
 
         let frame_0_inputs = rollback_manager.get_frame_inputs(1);
-        assert_eq!(frame_0_inputs.get(&P1ID.clone()), Some(&1));
-        assert_eq!(frame_0_inputs.get(&P2ID.clone()), Some(&2));
+        assert_eq!(frame_0_inputs.get(&P1ID), Some(&1));
+        assert_eq!(frame_0_inputs.get(&P2ID), Some(&2));
 
         let frame_0_inputs = rollback_manager.get_frame_inputs(2);
-        assert_eq!(frame_0_inputs.get(&P1ID.clone()), Some(&0));
-        assert_eq!(frame_0_inputs.get(&P2ID.clone()), Some(&0));
+        assert_eq!(frame_0_inputs.get(&P1ID), Some(&0));
+        assert_eq!(frame_0_inputs.get(&P2ID), Some(&0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ProgressFrame_OrderDependentUpdate_MatchesAcrossArrivalOrders() -> Result<()> {
+        // Each player's input is weighted by its position, so the state depends on iteration order
+        let ordered_update = |inputs: &FrameInputs<Input, u32>, state: State| {
+            inputs.values().fold(state * 7, |state, input| state * 3 + input)
+        };
+        let ids = [40_000u32, 7, 123, 9_999];
+
+        let mut forward = RollbackStateManager::new(0, 8);
+        let mut backward = RollbackStateManager::new(0, 8);
+        for frame in 0..6 {
+            for (index, id) in ids.iter().enumerate() {
+                forward.handle_input(frame, *id, (frame + index) as u64)?;
+            }
+            for (index, id) in ids.iter().enumerate().rev() {
+                backward.handle_input(frame, *id, (frame + index) as u64)?;
+            }
+            forward.progress_frame(ordered_update)?;
+            backward.progress_frame(ordered_update)?;
+        }
+
+        assert_eq!(forward.get_frame_inputs(3).ids().copied().collect::<Vec<_>>(), vec![7, 123, 9_999, 40_000]);
+        assert_eq!(forward.current_frame_state, backward.current_frame_state);
 
         Ok(())
     }
@@ -171,10 +1012,10 @@ mod tests {
     fn ProgressFrame_ComputesCorrectState() -> Result<()> {
         let mut rollback_manager = RollbackStateManager::new(1, 4);
 
-        rollback_manager.handle_input(1, P1ID.clone(), 1)?;
-        rollback_manager.handle_input(2, P2ID.clone(), 2)?;
-        rollback_manager.handle_input(3, P1ID.clone(), 0)?;
-        rollback_manager.handle_input(3, P2ID.clone(), 0)?;
+        rollback_manager.handle_input(1, P1ID, 1)?;
+        rollback_manager.handle_input(2, P2ID, 2)?;
+        rollback_manager.handle_input(3, P1ID, 0)?;
+        rollback_manager.handle_input(3, P2ID, 0)?;
 
         // frame 1 update
         // 1 + (1 + 0) = 2
@@ -184,15 +1025,15 @@ mod tests {
         assert_eq!(rollback_manager.current_frame_index, 0);
         assert_eq!(rollback_manager.current_frame_state, 1);
         
-        rollback_manager.progress_frame(update);
+        rollback_manager.progress_frame(update)?;
         assert_eq!(rollback_manager.current_frame_index, 1);
         assert_eq!(rollback_manager.current_frame_state, 2);
 
-        rollback_manager.progress_frame(update);
+        rollback_manager.progress_frame(update)?;
         assert_eq!(rollback_manager.current_frame_index, 2);
         assert_eq!(rollback_manager.current_frame_state, 5);
 
-        rollback_manager.progress_frame(update);
+        rollback_manager.progress_frame(update)?;
         assert_eq!(rollback_manager.current_frame_index, 3);
         assert_eq!(rollback_manager.current_frame_state, 5);
 
@@ -203,49 +1044,687 @@ mod tests {
     fn ProgressFrame_PastOldestFrame_PreservesInput() -> Result<()> {
         let mut rollback_manager = RollbackStateManager::new(0, 3);
 
-        rollback_manager.handle_input(1, P1ID.clone(), 1)?;
-        rollback_manager.handle_input(3, P1ID.clone(), 0)?;
+        rollback_manager.handle_input(1, P1ID, 1)?;
+        rollback_manager.handle_input(3, P1ID, 0)?;
 
         assert_eq!(rollback_manager.get_frame_inputs(1).get(&P1ID), Some(&1));
         assert_eq!(rollback_manager.get_frame_inputs(2).get(&P1ID), Some(&1));
         assert_eq!(rollback_manager.get_frame_inputs(3).get(&P1ID), Some(&0));
         assert_eq!(rollback_manager.get_frame_inputs(4).get(&P1ID), Some(&0));
 
-        rollback_manager.progress_frame(update);
+        rollback_manager.progress_frame(update)?;
         assert_eq!(rollback_manager.current_frame_index, 1);
         assert_eq!(rollback_manager.current_frame_state, 1);
         assert_eq!(rollback_manager.oldest_frame_index, 0);
 
-        rollback_manager.progress_frame(update);
+        rollback_manager.progress_frame(update)?;
         assert_eq!(rollback_manager.current_frame_index, 2);
         assert_eq!(rollback_manager.current_frame_state, 2);
         assert_eq!(rollback_manager.oldest_frame_index, 0);
 
-        rollback_manager.progress_frame(update);
+        rollback_manager.progress_frame(update)?;
         assert_eq!(rollback_manager.current_frame_index, 3);
         assert_eq!(rollback_manager.current_frame_state, 2);
         assert_eq!(rollback_manager.oldest_frame_index, 0);
 
-        rollback_manager.progress_frame(update);
+        rollback_manager.progress_frame(update)?;
         assert_eq!(rollback_manager.current_frame_index, 4);
         assert_eq!(rollback_manager.current_frame_state, 2);
         assert_eq!(rollback_manager.oldest_frame_index, 1);
 
-        rollback_manager.progress_frame(update);
+        rollback_manager.progress_frame(update)?;
         assert_eq!(rollback_manager.current_frame_index, 5);
         assert_eq!(rollback_manager.current_frame_state, 2);
         assert_eq!(rollback_manager.oldest_frame_index, 2);
 
-        rollback_manager.progress_frame(update);
+        rollback_manager.progress_frame(update)?;
         assert_eq!(rollback_manager.current_frame_index, 6);
         assert_eq!(rollback_manager.current_frame_state, 2);
         assert_eq!(rollback_manager.oldest_frame_index, 3);
 
-        rollback_manager.progress_frame(update);
+        rollback_manager.progress_frame(update)?;
         assert_eq!(rollback_manager.current_frame_index, 7);
         assert_eq!(rollback_manager.current_frame_state, 2);
         assert_eq!(rollback_manager.oldest_frame_index, 4);
 
         Ok(())
     }
+
+    #[test]
+    fn ProgressFrame_NoLateInput_UpdatesOnce() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::new(0, 8);
+        let update_count = Cell::new(0);
+        let counted_update = |input: &FrameInputs<Input, Id>, state: State| {
+            update_count.set(update_count.get() + 1);
+            update(input, state)
+        };
+
+        rollback_manager.handle_input(0, P1ID, 1)?;
+        for _ in 0..20 {
+            rollback_manager.progress_frame(counted_update)?;
+        }
+
+        // The first tick simulates frames 0 and 1, every tick after simulates only the new frame
+        assert_eq!(update_count.get(), 21);
+        assert_eq!(rollback_manager.current_frame_state, 21);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ProgressFrame_LateInput_ResimulatesFromChangedFrame() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::new(0, 8);
+        let update_count = Cell::new(0);
+        let counted_update = |input: &FrameInputs<Input, Id>, state: State| {
+            update_count.set(update_count.get() + 1);
+            update(input, state)
+        };
+
+        for _ in 0..6 {
+            rollback_manager.progress_frame(counted_update)?;
+        }
+        update_count.set(0);
+
+        rollback_manager.handle_input(4, P1ID, 2)?;
+        rollback_manager.progress_frame(counted_update)?;
+
+        // Frames 4 through 6 are simulated again along with the new frame 7
+        assert_eq!(update_count.get(), 4);
+        assert_eq!(rollback_manager.current_frame_state, 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ProgressFrame_SnapshotInterval_MatchesEveryFrameSnapshots() -> Result<()> {
+        let mut every_frame = RollbackStateManager::new(0, 8);
+        let mut every_fourth_frame = RollbackStateManager::with_snapshot_interval(0, 8, 4);
+
+        for frame in 0..40usize {
+            if frame % 3 == 0 {
+                // Late input a few frames in the past
+                let late_frame = frame.saturating_sub(5);
+                every_frame.handle_input(late_frame, P1ID, frame as u64)?;
+                every_fourth_frame.handle_input(late_frame, P1ID, frame as u64)?;
+            }
+
+            every_frame.progress_frame(update)?;
+            every_fourth_frame.progress_frame(update)?;
+            assert_eq!(every_frame.current_frame_state, every_fourth_frame.current_frame_state);
+        }
+
+        assert!(every_fourth_frame.snapshots.len() <= every_fourth_frame.snapshots.capacity());
+        Ok(())
+    }
+
+    #[test]
+    fn ProgressFrame_SnapshotInterval_ResimulatesFromSnapshot() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::with_snapshot_interval(0, 16, 4);
+        let update_count = Cell::new(0);
+        let counted_update = |input: &FrameInputs<Input, Id>, state: State| {
+            update_count.set(update_count.get() + 1);
+            update(input, state)
+        };
+
+        for _ in 0..10 {
+            rollback_manager.progress_frame(counted_update)?;
+        }
+        update_count.set(0);
+
+        // Frame 7 rolls back to the snapshot after frame 4, then simulates frames 5 through 11
+        rollback_manager.handle_input(7, P1ID, 1)?;
+        rollback_manager.progress_frame(counted_update)?;
+        assert_eq!(update_count.get(), 7);
+        assert_eq!(rollback_manager.current_frame_state, 5);
+
+        Ok(())
+    }
+
+    // Large state which can't be cloned, so it's copied through SaveState instead
+    #[derive(Debug)]
+    struct World {
+        totals: Vec<u64>
+    }
+
+    impl SaveState for World {
+        fn save_state(&self) -> World {
+            World {
+                totals: self.totals.to_vec()
+            }
+        }
+
+        fn load_state(&mut self, saved: &World) {
+            self.totals.clear();
+            self.totals.extend_from_slice(&saved.totals);
+        }
+    }
+
+    fn update_in_place(input: &FrameInputs<Input, Id>, world: &mut World) {
+        for input_value in input.values() {
+            world.totals[(input_value % 4) as usize] += input_value;
+        }
+    }
+
+    #[test]
+    fn ProgressFrameMut_LateInputs_MatchesProgressFrame() -> Result<()> {
+        let mut by_value = RollbackStateManager::with_snapshot_interval(0, 8, 2);
+        let mut in_place = RollbackStateManager::with_snapshot_interval(World { totals: vec![0; 4] }, 8, 2);
+
+        for frame in 1..20 {
+            by_value.handle_input(frame, P1ID, frame as u64)?;
+            in_place.handle_input(frame, P1ID, frame as u64)?;
+            if frame % 5 == 0 {
+                by_value.handle_input(frame - 3, P2ID, 7)?;
+                in_place.handle_input(frame - 3, P2ID, 7)?;
+            }
+
+            let report = by_value.progress_frame(update)?.into_rollback();
+            assert_eq!(in_place.progress_frame_mut(update_in_place)?.into_rollback(), report);
+            assert_eq!(in_place.current_frame_state.totals.iter().sum::<u64>(), by_value.current_frame_state);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn ProgressFrame_MispredictedInput_ReportsRollback() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::new(0, 8);
+
+        rollback_manager.handle_input(0, P1ID, 1)?;
+        rollback_manager.handle_input(0, P2ID, 1)?;
+        assert_eq!(rollback_manager.progress_frame(update)?.into_rollback(), None);
+        for _ in 0..5 {
+            assert_eq!(rollback_manager.progress_frame(update)?.into_rollback(), None);
+        }
+
+        // Input for a future frame is not a misprediction
+        rollback_manager.handle_input(8, P1ID, 3)?;
+        rollback_manager.handle_input(4, P2ID, 2)?;
+        rollback_manager.handle_input(3, P1ID, 2)?;
+
+        let report = rollback_manager.progress_frame(update)?.into_rollback();
+        let mut players = vec![P1ID, P2ID];
+        players.sort();
+        assert_eq!(report, Some(RollbackReport {
+            earliest_mispredicted_frame: 3,
+            frames_resimulated: 4,
+            players
+        }));
+        assert_eq!(rollback_manager.progress_frame(update)?.into_rollback(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn HandleInput_LateInputMatchesPrediction_SkipsRollback() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::new(0, 8);
+        let update_count = Cell::new(0);
+        let counted_update = |input: &FrameInputs<Input, Id>, state: State| {
+            update_count.set(update_count.get() + 1);
+            update(input, state)
+        };
+
+        rollback_manager.handle_input(0, P1ID, 1)?;
+        for _ in 0..6 {
+            rollback_manager.progress_frame(counted_update)?;
+        }
+        update_count.set(0);
+
+        // Held input arriving late is identical to the carried forward prediction
+        rollback_manager.handle_input(3, P1ID, 1)?;
+        rollback_manager.handle_input(5, P1ID, 1)?;
+        assert_eq!(rollback_manager.progress_frame(counted_update)?.into_rollback(), None);
+        assert_eq!(update_count.get(), 1);
+        assert_eq!(rollback_manager.current_frame_state, 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ProgressFrame_DefaultInputPredictor_PredictsRelease() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::with_predictor(0, 8, 1, DefaultInput);
+
+        rollback_manager.handle_input(1, P1ID, 3)?;
+        assert_eq!(rollback_manager.get_frame_inputs(1).get(&P1ID), Some(&3));
+        assert_eq!(rollback_manager.get_frame_inputs(2).get(&P1ID), Some(&0));
+
+        for _ in 0..4 {
+            rollback_manager.progress_frame(update)?;
+        }
+        assert_eq!(rollback_manager.current_frame_state, 3);
+
+        // A late neutral input matches the prediction, a late press does not
+        rollback_manager.handle_input(3, P1ID, 0)?;
+        rollback_manager.handle_input(4, P1ID, 2)?;
+        let report = rollback_manager.progress_frame(update)?.into_rollback();
+        assert_eq!(report.map(|report| report.earliest_mispredicted_frame), Some(4));
+        assert_eq!(rollback_manager.current_frame_state, 5);
+
+        Ok(())
+    }
+
+    // Predict the average of the last two received inputs
+    fn average(history: &InputHistory<Input, Id>) -> Option<Input> {
+        let inputs: Vec<Input> = history.iter().take(2).map(|(_, input)| *input).collect();
+        if inputs.is_empty() {
+            None
+        } else {
+            Some(inputs.iter().sum::<Input>() / inputs.len() as Input)
+        }
+    }
+
+    #[test]
+    fn ProgressFrame_ClosurePredictor_SeesHistory() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::with_predictor(0, 8, 1, average);
+
+        rollback_manager.handle_input(0, P1ID, 2)?;
+        rollback_manager.handle_input(1, P1ID, 6)?;
+        assert_eq!(rollback_manager.get_frame_inputs(2).get(&P1ID), Some(&4));
+
+        for _ in 0..3 {
+            rollback_manager.progress_frame(update)?;
+        }
+        assert_eq!(rollback_manager.current_frame_state, 2 + 6 + 4 + 4);
+
+        // Confirming the predicted input for frame 2 changes the prediction for frame 3
+        rollback_manager.handle_input(2, P1ID, 4)?;
+        let report = rollback_manager.progress_frame(update)?.into_rollback();
+        assert_eq!(report.map(|report| report.earliest_mispredicted_frame), Some(3));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ProgressFrame_WindowSlideChangesPrediction_ResimulatesFrames() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::with_predictor(0, 2, 1, average);
+
+        rollback_manager.handle_input(0, P1ID, 2)?;
+        rollback_manager.handle_input(1, P1ID, 6)?;
+        for _ in 0..3 {
+            rollback_manager.progress_frame(update)?;
+        }
+        assert_eq!(rollback_manager.oldest_frame_index, 1);
+
+        // Frame 0 left the window, so frames 2 and 3 are predicted from frame 1 alone
+        let report = rollback_manager.progress_frame(update)?.into_rollback();
+        assert_eq!(report.map(|report| report.earliest_mispredicted_frame), Some(2));
+        assert_eq!(rollback_manager.get_frame_inputs(3).get(&P1ID), Some(&6));
+        assert_eq!(rollback_manager.current_frame_state, 2 + 6 * 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn HandleInput_ConfirmedFrame_TracksSlowestPlayer() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::new(0, 8);
+        assert_eq!(rollback_manager.confirmed_frame(), None);
+
+        rollback_manager.handle_input(0, P1ID, 1)?;
+        rollback_manager.handle_input(3, P1ID, 1)?;
+        assert_eq!(rollback_manager.confirmed_frame(), Some(0));
+
+        // Frames after a gap are confirmed once the gap is filled
+        rollback_manager.handle_input(2, P1ID, 1)?;
+        rollback_manager.handle_input(1, P1ID, 1)?;
+        assert_eq!(rollback_manager.confirmed_frame(), Some(3));
+
+        // A player's first inputs can arrive out of order too, so their inputs are expected from
+        // the start of the window
+        rollback_manager.handle_input(1, P2ID, 1)?;
+        assert_eq!(rollback_manager.confirmed_frame(), None);
+        rollback_manager.handle_input(0, P2ID, 1)?;
+        assert_eq!(rollback_manager.confirmed_frames.get(&P1ID), Some(&3));
+        assert_eq!(rollback_manager.confirmed_frames.get(&P2ID), Some(&1));
+        assert_eq!(rollback_manager.confirmed_frame(), Some(1));
+
+        rollback_manager.handle_input(4, P2ID, 1)?;
+        rollback_manager.handle_input(2, P2ID, 1)?;
+        assert_eq!(rollback_manager.confirmed_frame(), Some(2));
+        assert_eq!(rollback_manager.get_frame_inputs(3).status(&P2ID), Some(InputStatus::Predicted));
+        rollback_manager.handle_input(3, P2ID, 1)?;
+        assert_eq!(rollback_manager.confirmed_frame(), Some(3));
+        assert!(!rollback_manager.get_frame_inputs(3).has_predictions());
+
+        Ok(())
+    }
+
+    #[test]
+    fn AddPlayer_RemovePlayer_ChangesPlayerSet() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::new(0, 8);
+
+        rollback_manager.handle_input(0, P1ID, 1)?;
+        rollback_manager.add_player(P2ID, 2)?;
+        rollback_manager.handle_input(3, P2ID, 2)?;
+        rollback_manager.remove_player(P1ID, 4)?;
+
+        assert!(!rollback_manager.get_frame_inputs(1).contains_key(&P2ID));
+        assert_eq!(rollback_manager.get_frame_inputs(3).get(&P1ID), Some(&1));
+        assert_eq!(rollback_manager.get_frame_inputs(3).get(&P2ID), Some(&2));
+        assert!(!rollback_manager.get_frame_inputs(4).contains_key(&P1ID));
+        assert_eq!(rollback_manager.get_frame_inputs(4).get(&P2ID), Some(&2));
+
+        // Player 1 still holds back confirmation for the frames before leaving, while player 2
+        // hasn't sent frame 2
+        assert_eq!(rollback_manager.confirmed_frame(), Some(0));
+        for _ in 0..5 {
+            rollback_manager.progress_frame(update)?;
+        }
+        assert_eq!(rollback_manager.players_waiting_for(4), vec![P1ID, P2ID]);
+        for frame in 1..4 {
+            rollback_manager.handle_input(frame, P1ID, 1)?;
+        }
+        assert_eq!(rollback_manager.confirmed_frame(), Some(1));
+        assert_eq!(rollback_manager.players_waiting_for(6), vec![P2ID]);
+
+        // Frames 0 through 3 have player 1 pressing 1, frames 3 through 5 have player 2 pressing 2
+        assert_eq!(rollback_manager.current_frame_state, 4 + 3 * 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn AddPlayer_AlreadySendingInputs_PresentFromFirstInput() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::with_predictor(0, 8, 1, DefaultInput);
+
+        rollback_manager.handle_input(0, P1ID, 1)?;
+        rollback_manager.handle_input(5, P2ID, 3)?;
+        assert!(!rollback_manager.get_frame_inputs(2).contains_key(&P2ID));
+
+        // Joining later than the first input keeps the player from that input onward, without
+        // making them present on the frames before it
+        rollback_manager.add_player(P2ID, 7)?;
+        assert!(!rollback_manager.get_frame_inputs(2).contains_key(&P2ID));
+        assert!(!rollback_manager.get_frame_inputs(4).contains_key(&P2ID));
+        assert_eq!(rollback_manager.get_frame_inputs(5).get(&P2ID), Some(&3));
+        assert_eq!(rollback_manager.get_frame_inputs(6).get(&P2ID), Some(&0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn RemovePlayer_LateLeave_RollsBack() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::new(0, 16);
+
+        rollback_manager.handle_input(0, P1ID, 1)?;
+        rollback_manager.handle_input(0, P2ID, 1)?;
+        for _ in 0..10 {
+            rollback_manager.progress_frame(update)?;
+        }
+        assert_eq!(rollback_manager.current_frame_state, 22);
+
+        rollback_manager.remove_player(P2ID, 6)?;
+        let report = rollback_manager.progress_frame(update)?.into_rollback();
+        assert_eq!(report, Some(RollbackReport {
+            earliest_mispredicted_frame: 6,
+            frames_resimulated: 5,
+            players: vec![P2ID]
+        }));
+        assert_eq!(rollback_manager.current_frame_state, 12 + 6);
+
+        // The departed player's inputs no longer have any effect
+        rollback_manager.handle_input(8, P2ID, 5)?;
+        assert_eq!(rollback_manager.progress_frame(update)?.into_rollback(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn ProgressFrame_ConfirmedInputs_ReportsConfirmedFrames() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::new(0, 4);
+        for frame in 0..3 {
+            rollback_manager.handle_input(frame, P1ID, 1)?;
+            rollback_manager.handle_input(frame, P2ID, 1)?;
+        }
+
+        // The first progress simulates frames 0 and 1
+        assert_eq!(rollback_manager.progress_frame(update)?.confirmed_frames(), 0..2);
+        assert_eq!(rollback_manager.progress_frame(update)?.confirmed_frames(), 2..3);
+        rollback_manager.handle_input(3, P1ID, 1)?;
+        assert_eq!(rollback_manager.progress_frame(update)?.confirmed_frames(), 3..3);
+        rollback_manager.handle_input(3, P2ID, 1)?;
+        rollback_manager.handle_input(4, P1ID, 1)?;
+        rollback_manager.handle_input(4, P2ID, 1)?;
+        assert_eq!(rollback_manager.progress_frame(update)?.confirmed_frames(), 3..5);
+
+        // Player 2's inputs stay predicted after the inputs they were predicted from are forgotten
+        for frame in 5..12 {
+            rollback_manager.handle_input(frame, P1ID, 1)?;
+            assert!(rollback_manager.progress_frame(update)?.confirmed_frames().is_empty());
+        }
+        let oldest_frame_index = rollback_manager.oldest_frame_index;
+        assert!(oldest_frame_index > 5);
+        let inputs = rollback_manager.get_frame_inputs(oldest_frame_index);
+        assert_eq!(inputs.status(&P1ID), Some(InputStatus::Confirmed));
+        assert_eq!(inputs.status(&P2ID), Some(InputStatus::Predicted));
+
+        // Frames which left the window unconfirmed are never reported
+        for frame in oldest_frame_index..12 {
+            rollback_manager.handle_input(frame, P2ID, 1)?;
+        }
+        assert_eq!(rollback_manager.progress_frame(update)?.confirmed_frames(), oldest_frame_index..12);
+        assert!(!rollback_manager.get_frame_inputs(oldest_frame_index).has_predictions());
+
+        Ok(())
+    }
+
+    #[test]
+    fn DisconnectPlayer_LateDisconnect_MarksPredictedInputsDisconnected() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::new(0, 16);
+
+        rollback_manager.handle_input(0, P1ID, 1)?;
+        rollback_manager.handle_input(0, P2ID, 1)?;
+        for frame in 1..11 {
+            rollback_manager.handle_input(frame, P1ID, 1)?;
+        }
+        for _ in 0..10 {
+            rollback_manager.progress_frame(update)?;
+        }
+        let inputs = rollback_manager.get_frame_inputs(6);
+        assert_eq!(inputs.status(&P1ID), Some(InputStatus::Confirmed));
+        assert_eq!(inputs.status(&P2ID), Some(InputStatus::Predicted));
+        assert!(inputs.has_predictions());
+        assert_eq!(rollback_manager.confirmed_frame(), Some(0));
+
+        // The held input doesn't change, but frames seen with a predicted status are simulated again
+        rollback_manager.disconnect_player(P2ID, 6)?;
+        let report = rollback_manager.progress_frame(update)?.into_rollback();
+        assert_eq!(report, Some(RollbackReport {
+            earliest_mispredicted_frame: 6,
+            frames_resimulated: 5,
+            players: vec![P2ID]
+        }));
+        assert_eq!(rollback_manager.current_frame_state, 12 * 2);
+
+        let inputs = rollback_manager.get_frame_inputs(6);
+        assert_eq!(inputs.get(&P2ID), Some(&1));
+        assert_eq!(inputs.status(&P2ID), Some(InputStatus::Disconnected));
+        assert_eq!(rollback_manager.get_frame_inputs(5).status(&P2ID), Some(InputStatus::Predicted));
+
+        // Frames before disconnecting still need player 2's inputs to be confirmed
+        assert_eq!(rollback_manager.confirmed_frame(), Some(0));
+        for frame in 1..6 {
+            rollback_manager.handle_input(frame, P2ID, 1)?;
+        }
+        assert_eq!(rollback_manager.confirmed_frame(), Some(10));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ProgressFrame_WaitForConfirmation_StallsOnLaggingPlayer() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::new(0, 4);
+        rollback_manager.wait_for_confirmation = true;
+
+        rollback_manager.handle_input(0, P1ID, 1)?;
+        rollback_manager.handle_input(0, P2ID, 1)?;
+        for frame in 1..8 {
+            rollback_manager.handle_input(frame, P1ID, 1)?;
+        }
+
+        // Frame 5 only needs frame 0 to be confirmed
+        for _ in 0..5 {
+            rollback_manager.progress_frame(update)?;
+        }
+        assert_eq!(rollback_manager.progress_frame(update)?, Progress::WaitingOnPlayers {
+            frame: 6,
+            players: vec![P2ID]
+        });
+        assert_eq!(rollback_manager.current_frame_index, 5);
+        assert_eq!(rollback_manager.oldest_frame_index, 1);
+
+        // Player 2's late input is still accepted and corrects the frames it was predicted for
+        rollback_manager.handle_input(1, P2ID, 2)?;
+        rollback_manager.progress_frame(update)?;
+        assert_eq!(rollback_manager.current_frame_index, 6);
+        assert_eq!(rollback_manager.oldest_frame_index, 2);
+        assert_eq!(rollback_manager.current_frame_state, 2 + 6 * 3);
+
+        Ok(())
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    struct CountedState(u64);
+
+    impl Checksum for CountedState {
+        fn checksum(&self) -> u64 {
+            hash_checksum(self)
+        }
+    }
+
+    fn counted_update(input: &FrameInputs<Input, Id>, state: CountedState) -> CountedState {
+        CountedState(update(input, state.0))
+    }
+
+    #[test]
+    fn HandleRemoteChecksum_ConfirmedFrames_DetectsDesync() -> Result<()> {
+        let mut local = RollbackStateManager::new(CountedState(0), 8);
+        let mut remote = RollbackStateManager::new(CountedState(0), 8);
+        local.enable_checksums();
+        remote.enable_checksums();
+
+        for frame in 0..6 {
+            local.handle_input(frame, P1ID, 1)?;
+            // The remote peer diverges from frame 3 onward
+            remote.handle_input(frame, P1ID, if frame < 3 { 1 } else { 2 })?;
+        }
+        for _ in 0..5 {
+            local.progress_frame(counted_update)?;
+            remote.progress_frame(counted_update)?;
+        }
+
+        // Frame 6 hasn't been confirmed so it has no comparable checksum
+        assert!(local.confirmed_checksum(6).is_none());
+        for frame in 0..3 {
+            let remote_checksum = remote.confirmed_checksum(frame).unwrap();
+            assert_eq!(local.confirmed_checksum(frame), Some(remote_checksum));
+            local.handle_remote_checksum(frame, remote_checksum)?;
+        }
+
+        let remote_checksum = remote.confirmed_checksum(3).unwrap();
+        match local.handle_remote_checksum(3, remote_checksum) {
+            Err(RollbackError::Desync { frame, local, remote }) => {
+                assert_eq!(frame, 3);
+                assert_eq!(remote, remote_checksum);
+                assert_ne!(local, remote);
+            },
+            result => panic!("Expected desync, got {:?}", result)
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn ProgressFrame_PendingRemoteChecksum_ComparedOnceConfirmed() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::new(CountedState(0), 8);
+        rollback_manager.enable_checksums();
+
+        rollback_manager.handle_input(0, P1ID, 1)?;
+        rollback_manager.progress_frame(counted_update)?;
+
+        // Frame 1 was only predicted, so the comparison waits. The progress which confirms it
+        // still advances and reports the desync alongside the confirmed frames
+        rollback_manager.handle_remote_checksum(1, 0)?;
+        rollback_manager.handle_input(1, P1ID, 1)?;
+        let progress = rollback_manager.progress_frame(counted_update)?;
+        assert_eq!(progress.desync().map(|desync| desync.frame), Some(1));
+        assert_eq!(progress.confirmed_frames(), 1..2);
+        assert_eq!(rollback_manager.current_frame_index, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn Replay_PlayedBack_ReproducesCurrentState() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::with_snapshot_interval(3, 4, 2);
+        rollback_manager.record_replay();
+
+        rollback_manager.handle_input(0, P1ID, 1)?;
+        for frame in 0..30usize {
+            if frame % 4 == 0 {
+                rollback_manager.handle_input(frame.saturating_sub(2), P2ID, frame as u64)?;
+            }
+            if frame == 12 {
+                rollback_manager.remove_player(P1ID, 11)?;
+            }
+            rollback_manager.progress_frame(update)?;
+        }
+
+        let replay = rollback_manager.replay().unwrap();
+        assert_eq!(replay.start_frame, 0);
+        assert_eq!(replay.initial_state, 3);
+        assert_eq!(replay.last_frame(), Some(30));
+        assert_eq!(replay.play(update), rollback_manager.current_frame_state);
+        assert!(!replay.frame_inputs[20].contains_key(&P1ID));
+
+        Ok(())
+    }
+
+    #[test]
+    fn Replay_ClosurePredictor_RecordsSimulatedInputs() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::with_predictor(0, 2, 1, average);
+        rollback_manager.record_replay();
+
+        // Predictions change once the inputs they averaged leave the window, before the frames
+        // are simulated again, so the replay needs the inputs which were actually simulated
+        rollback_manager.handle_input(0, P1ID, 2)?;
+        rollback_manager.handle_input(1, P1ID, 6)?;
+        for frame in 1..9 {
+            rollback_manager.progress_frame(update)?;
+            let replay = rollback_manager.replay().unwrap();
+            assert_eq!(replay.last_frame(), Some(frame));
+            assert_eq!(replay.play(update), rollback_manager.current_frame_state);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn Serialize_Deserialize_ResumesSession() -> Result<()> {
+        let mut rollback_manager = RollbackStateManager::with_snapshot_interval(CountedState(0), 8, 2);
+        rollback_manager.enable_checksums();
+        rollback_manager.record_replay();
+        rollback_manager.add_player(P2ID, 3)?;
+        for frame in 0..12 {
+            rollback_manager.handle_input(frame, P1ID, frame as u64 % 3)?;
+            rollback_manager.progress_frame(counted_update)?;
+        }
+
+        let serialized = serde_json::to_string(&rollback_manager).unwrap();
+        let mut restored: RollbackStateManager<Input, CountedState, RepeatLastInput, Id> = serde_json::from_str(&serialized).unwrap();
+        assert!(restored.checksum_state.is_none());
+        restored.enable_checksums();
+
+        for frame in 12..20 {
+            rollback_manager.handle_input(frame - 2, P2ID, 1)?;
+            restored.handle_input(frame - 2, P2ID, 1)?;
+            rollback_manager.progress_frame(counted_update)?;
+            restored.progress_frame(counted_update)?;
+        }
+        assert_eq!(restored.current_frame_state, rollback_manager.current_frame_state);
+        assert_eq!(restored.replay(), rollback_manager.replay());
+
+        Ok(())
+    }
 }
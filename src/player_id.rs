@@ -0,0 +1,14 @@
+use core::fmt::Debug;
+use std::hash::Hash;
+
+// Identifies a player. Anything comparable and hashable works, from UUIDs to small integer slots
+// for local play
+pub trait PlayerId: Eq + Hash + Ord + Clone + Debug {}
+
+impl<T: Eq + Hash + Ord + Clone + Debug> PlayerId for T {}
+
+// Player id used when none is given
+#[cfg(feature = "uuid")]
+pub type DefaultPlayerId = uuid::Uuid;
+#[cfg(not(feature = "uuid"))]
+pub type DefaultPlayerId = u32;
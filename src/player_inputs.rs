@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, VecDeque};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// Received input for a frame, along with the newest frame at or before it with a received input.
+// Predicted inputs carried over from forgotten frames count as received for finding the newest
+// input, but are kept marked as predicted
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+struct FrameSlot<Input> {
+    input: Option<Input>,
+    predicted: bool,
+    newest_received: Option<usize>
+}
+
+// Inputs received from a single player. Frames from the oldest frame of the rollback window live
+// in a ring buffer indexed by frame, so finding a frame's input or the newest input before it takes
+// constant time. Inputs for frames too far ahead to fit wait in a map until the window reaches them
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PlayerInputs<Input> {
+    oldest_frame: usize,
+    // Most frames kept in the ring buffer
+    capacity: usize,
+    slots: VecDeque<FrameSlot<Input>>,
+    future_inputs: BTreeMap<usize, Input>
+}
+
+impl<Input> PlayerInputs<Input> {
+    pub fn new(oldest_frame: usize, capacity: usize) -> PlayerInputs<Input> {
+        PlayerInputs {
+            oldest_frame,
+            capacity: capacity.max(1),
+            slots: VecDeque::new(),
+            future_inputs: BTreeMap::new()
+        }
+    }
+
+    pub fn oldest_frame(&self) -> usize {
+        self.oldest_frame
+    }
+
+    // Record the input for a frame, replacing any input already received for it. Frames before the
+    // oldest frame are ignored
+    pub fn insert(&mut self, frame: usize, input: Input) {
+        self.insert_slot(frame, input, false);
+    }
+
+    // Record a predicted input for a frame which has no received input, such as the input carried
+    // over from forgotten frames. It's used like a received input, but keeps its predicted status
+    pub fn insert_predicted(&mut self, frame: usize, input: Input) {
+        if self.get(frame).is_none() {
+            self.insert_slot(frame, input, true);
+        }
+    }
+
+    // Whether the input held for a frame is a prediction rather than a received input
+    pub fn is_predicted(&self, frame: usize) -> bool {
+        frame.checked_sub(self.oldest_frame)
+            .and_then(|offset| self.slots.get(offset))
+            .is_some_and(|slot| slot.predicted)
+    }
+
+    fn insert_slot(&mut self, frame: usize, input: Input, predicted: bool) {
+        if frame < self.oldest_frame {
+            return;
+        }
+        let offset = frame - self.oldest_frame;
+        if offset >= self.capacity {
+            self.future_inputs.insert(frame, input);
+            return;
+        }
+
+        while self.slots.len() <= offset {
+            let newest_received = self.slots.back().and_then(|slot| slot.newest_received);
+            self.slots.push_back(FrameSlot { input: None, predicted: false, newest_received });
+        }
+        self.slots[offset].input = Some(input);
+        self.slots[offset].predicted = predicted;
+
+        // Later frames up to the next received input now have this one as their newest
+        for slot in self.slots.range_mut(offset..) {
+            if slot.newest_received.is_some_and(|newest_received| newest_received > frame) {
+                break;
+            }
+            slot.newest_received = Some(frame);
+        }
+    }
+
+    pub fn get(&self, frame: usize) -> Option<&Input> {
+        let offset = frame.checked_sub(self.oldest_frame)?;
+        if offset >= self.capacity {
+            return self.future_inputs.get(&frame);
+        }
+        self.slots.get(offset).and_then(|slot| slot.input.as_ref())
+    }
+
+    // Newest received input at or before the given frame, along with its frame
+    pub fn newest_at_or_before(&self, frame: usize) -> Option<(usize, &Input)> {
+        let offset = frame.checked_sub(self.oldest_frame)?;
+        if offset >= self.slots.len() {
+            if let Some((future_frame, input)) = self.future_inputs.range(..=frame).next_back() {
+                return Some((*future_frame, input));
+            }
+        }
+
+        let slot = self.slots.get(offset).or_else(|| self.slots.back())?;
+        let newest_received = slot.newest_received?;
+        self.get(newest_received).map(|input| (newest_received, input))
+    }
+
+    // Iterate over received inputs before the given frame from newest to oldest, along with
+    // their frames
+    pub fn iter_before(&self, frame: usize) -> impl Iterator<Item = (usize, &Input)> + '_ {
+        let mut next = frame.checked_sub(1).and_then(|previous_frame| self.newest_at_or_before(previous_frame));
+        std::iter::from_fn(move || {
+            let current = next.take()?;
+            next = current.0.checked_sub(1).and_then(|previous_frame| self.newest_at_or_before(previous_frame));
+            Some(current)
+        })
+    }
+
+    // Oldest frame with an input held for it
+    pub fn oldest_input_frame(&self) -> Option<usize> {
+        self.slots.iter()
+            .position(|slot| slot.input.is_some())
+            .map(|offset| self.oldest_frame + offset)
+            .or_else(|| self.future_inputs.keys().next().copied())
+    }
+
+    // Whether no inputs are held at all
+    pub fn is_empty(&self) -> bool {
+        self.slots.back().is_none_or(|slot| slot.newest_received.is_none()) && self.future_inputs.is_empty()
+    }
+
+    // Forget inputs before the new oldest frame
+    pub fn advance(&mut self, new_oldest_frame: usize) {
+        if new_oldest_frame <= self.oldest_frame {
+            return;
+        }
+
+        let removed_slots = (new_oldest_frame - self.oldest_frame).min(self.slots.len());
+        self.slots.drain(..removed_slots);
+        self.oldest_frame = new_oldest_frame;
+
+        // Frames with no received input since the forgotten ones no longer have a newest input
+        for slot in self.slots.iter_mut() {
+            if slot.newest_received.is_some_and(|newest_received| newest_received >= new_oldest_frame) {
+                break;
+            }
+            slot.newest_received = None;
+        }
+
+        let end_frame = new_oldest_frame + self.capacity;
+        while let Some((future_frame, _)) = self.future_inputs.iter().next() {
+            if *future_frame >= end_frame {
+                break;
+            }
+            let future_frame = *future_frame;
+            if let Some(input) = self.future_inputs.remove(&future_frame) {
+                self.insert(future_frame, input);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn NewestAtOrBefore_GapsAndFutureInputs_FindsNewestReceived() {
+        let mut inputs = PlayerInputs::new(10, 4);
+        inputs.insert(11, 'a');
+        inputs.insert(13, 'b');
+        inputs.insert(20, 'c');
+        inputs.insert(12, 'd');
+
+        assert_eq!(inputs.newest_at_or_before(10), None);
+        assert_eq!(inputs.newest_at_or_before(12), Some((12, &'d')));
+        assert_eq!(inputs.newest_at_or_before(13), Some((13, &'b')));
+        assert_eq!(inputs.newest_at_or_before(19), Some((13, &'b')));
+        assert_eq!(inputs.newest_at_or_before(25), Some((20, &'c')));
+        assert_eq!(inputs.get(20), Some(&'c'));
+        assert_eq!(inputs.iter_before(21).map(|(frame, _)| frame).collect::<Vec<_>>(), vec![20, 13, 12, 11]);
+    }
+
+    #[test]
+    fn Advance_PastInputs_ForgetsOldInputsAndPullsInFutureInputs() {
+        let mut inputs = PlayerInputs::new(0, 4);
+        inputs.insert(1, 'a');
+        inputs.insert(3, 'b');
+        inputs.insert(9, 'c');
+
+        inputs.advance(2);
+        assert_eq!(inputs.oldest_frame(), 2);
+        assert_eq!(inputs.get(1), None);
+        assert_eq!(inputs.newest_at_or_before(2), None);
+        assert_eq!(inputs.newest_at_or_before(4), Some((3, &'b')));
+
+        inputs.advance(6);
+        assert_eq!(inputs.newest_at_or_before(8), None);
+        assert_eq!(inputs.newest_at_or_before(9), Some((9, &'c')));
+
+        inputs.advance(10);
+        assert!(inputs.is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{DefaultPlayerId, PlayerId, PlayerInputs};
+
+// Received inputs for a single player from the oldest frame in the rollback window up to, but not
+// including, the frame being predicted
+pub struct InputHistory<'a, Input, Id = DefaultPlayerId> {
+    id: Id,
+    frame: usize,
+    inputs: Option<&'a PlayerInputs<Input>>
+}
+
+impl<'a, Input, Id: PlayerId> InputHistory<'a, Input, Id> {
+    pub fn new(id: Id, frame: usize, inputs: Option<&'a PlayerInputs<Input>>) -> InputHistory<'a, Input, Id> {
+        InputHistory { id, frame, inputs }
+    }
+
+    // Player the prediction is for
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+
+    // Frame the prediction is for
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    // Iterate over received inputs from newest to oldest along with the frame they were
+    // received for
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &'a Input)> + '_ {
+        let frame = self.frame;
+        self.inputs.into_iter().flat_map(move |inputs| inputs.iter_before(frame))
+    }
+
+    // Newest received input before the predicted frame
+    pub fn last(&self) -> Option<(usize, &'a Input)> {
+        let inputs = self.inputs?;
+        self.frame.checked_sub(1).and_then(|previous_frame| inputs.newest_at_or_before(previous_frame))
+    }
+}
+
+// Strategy for guessing a player's input on frames where it hasn't been received yet. Only called
+// for players who have sent an input or joined the game. Returning None leaves the player out of
+// the frame's inputs
+pub trait InputPredictor<Input, Id = DefaultPlayerId> {
+    fn predict(&self, history: &InputHistory<Input, Id>) -> Option<Input>;
+
+    // Whether predictions only look at the newest received input and not its frame. Older inputs
+    // are forgotten as the rollback window moves, which can change other predictions for frames
+    // already simulated, so those are checked and simulated again
+    fn uses_only_last_input(&self) -> bool {
+        false
+    }
+}
+
+// Predict that the player keeps holding whatever they last pressed
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RepeatLastInput;
+
+impl<Input: Clone, Id: PlayerId> InputPredictor<Input, Id> for RepeatLastInput {
+    fn predict(&self, history: &InputHistory<Input, Id>) -> Option<Input> {
+        history.last().map(|(_, input)| input.clone())
+    }
+
+    fn uses_only_last_input(&self) -> bool {
+        true
+    }
+}
+
+// Predict the neutral input
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DefaultInput;
+
+impl<Input: Default, Id> InputPredictor<Input, Id> for DefaultInput {
+    fn predict(&self, _history: &InputHistory<Input, Id>) -> Option<Input> {
+        Some(Input::default())
+    }
+
+    fn uses_only_last_input(&self) -> bool {
+        true
+    }
+}
+
+// Custom predictions from a closure
+impl<Input, Id, F> InputPredictor<Input, Id> for F where F: Fn(&InputHistory<Input, Id>) -> Option<Input> {
+    fn predict(&self, history: &InputHistory<Input, Id>) -> Option<Input> {
+        self(history)
+    }
+}
@@ -0,0 +1,66 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{DefaultPlayerId, FrameInputs, PlayerId, SaveState};
+
+// Every input given to the update function from the start frame onward, along with the state
+// before the start frame. Playing it back reproduces the recorded match exactly
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "Input: Serialize, State: Serialize, Id: Serialize",
+    deserialize = "Input: Deserialize<'de>, State: Deserialize<'de>, Id: Ord + Deserialize<'de>"
+)))]
+pub struct Replay<Input, State, Id = DefaultPlayerId> {
+    pub start_frame: usize,
+    pub initial_state: State,
+    pub frame_inputs: Vec<FrameInputs<Input, Id>>
+}
+
+impl<Input: Clone, State: SaveState, Id: PlayerId> Replay<Input, State, Id> {
+    pub fn new(start_frame: usize, initial_state: State) -> Replay<Input, State, Id> {
+        Replay {
+            start_frame,
+            initial_state,
+            frame_inputs: Vec::new()
+        }
+    }
+
+    // Copy of the replay which doesn't require the state to be Clone
+    pub fn save_replay(&self) -> Replay<Input, State, Id> {
+        Replay {
+            start_frame: self.start_frame,
+            initial_state: self.initial_state.save_state(),
+            frame_inputs: self.frame_inputs.clone()
+        }
+    }
+
+    // Newest frame with recorded inputs
+    pub fn last_frame(&self) -> Option<usize> {
+        if self.frame_inputs.is_empty() {
+            None
+        } else {
+            Some(self.start_frame + self.frame_inputs.len() - 1)
+        }
+    }
+
+    // Iterate over recorded frames and the inputs passed to update for them
+    pub fn frames(&self) -> impl Iterator<Item = (usize, &FrameInputs<Input, Id>)> {
+        let start_frame = self.start_frame;
+        self.frame_inputs.iter()
+            .enumerate()
+            .map(move |(offset, inputs)| (start_frame + offset, inputs))
+    }
+
+    // Simulate every recorded frame and return the final state
+    pub fn play<F>(&self, update: F) -> State where F: Fn(&FrameInputs<Input, Id>, State) -> State {
+        self.frames().fold(self.initial_state.save_state(), |state, (_, inputs)| update(inputs, state))
+    }
+
+    // Simulate recorded frames up to and including the given frame
+    pub fn play_until<F>(&self, frame: usize, update: F) -> State where F: Fn(&FrameInputs<Input, Id>, State) -> State {
+        self.frames()
+            .take_while(|(recorded_frame, _)| *recorded_frame <= frame)
+            .fold(self.initial_state.save_state(), |state, (_, inputs)| update(inputs, state))
+    }
+}
@@ -0,0 +1,19 @@
+// Copies game states for snapshots and rollbacks. Every Clone type gets this through Clone, while
+// large states such as ECS worlds which can't be cloned can implement it by hand with a cheaper
+// strategy, like reusing existing allocations when loading or sharing unchanged data between
+// copies. Games can also copy Clone states their own way through RollbackGame's save and load
+pub trait SaveState {
+    fn save_state(&self) -> Self;
+    // Overwrite this state with a previously saved one
+    fn load_state(&mut self, saved: &Self);
+}
+
+impl<T: Clone> SaveState for T {
+    fn save_state(&self) -> T {
+        self.clone()
+    }
+
+    fn load_state(&mut self, saved: &T) {
+        self.clone_from(saved);
+    }
+}
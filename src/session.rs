@@ -0,0 +1,763 @@
+use core::fmt::Debug;
+use std::collections::{BTreeSet, VecDeque};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+use crate::{ConfirmedFrames, DefaultPlayerId, FrameInputs, InputBatch, InputCodec, InputPredictor, Progress, RedundantInputQueue, RepeatLastInput, Result, RollbackGame, RollbackError, RollbackStateManager, SaveState, TimeSync, TimeSyncRecommendation, WirePlayerId, MAX_DATAGRAM_SIZE};
+
+// Non blocking datagram transport between peers
+pub trait Socket<Address> {
+    fn send_to(&mut self, bytes: &[u8], address: &Address) -> Result<()>;
+    // Copy the next waiting datagram into the buffer, or return None if nothing has arrived
+    fn receive_from(&mut self, buffer: &mut [u8]) -> Result<Option<(usize, Address)>>;
+}
+
+fn network_error(error: io::Error) -> RollbackError {
+    RollbackError::Network {
+        reason: error.to_string()
+    }
+}
+
+// Errors which only affect a single packet, like a full send buffer or an unreachable peer reported
+// for an earlier packet. The socket is still usable, so the packet is treated as lost
+fn is_transient_error(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted | io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted)
+}
+
+impl Socket<SocketAddr> for UdpSocket {
+    fn send_to(&mut self, bytes: &[u8], address: &SocketAddr) -> Result<()> {
+        match UdpSocket::send_to(self, bytes, address) {
+            Ok(_) => Ok(()),
+            Err(error) if is_transient_error(&error) => Ok(()),
+            Err(error) => Err(network_error(error))
+        }
+    }
+
+    fn receive_from(&mut self, buffer: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
+        loop {
+            match self.recv_from(buffer) {
+                Ok(received) => return Ok(Some(received)),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(error) if is_transient_error(&error) => continue,
+                Err(error) => return Err(network_error(error))
+            }
+        }
+    }
+}
+
+// Bind a non blocking UDP socket suitable for a session
+pub fn bind_udp_socket(address: SocketAddr) -> Result<UdpSocket> {
+    let socket = UdpSocket::bind(address).map_err(network_error)?;
+    socket.set_nonblocking(true).map_err(network_error)?;
+    Ok(socket)
+}
+
+// Remote peer along with the local inputs it hasn't acknowledged and the frames received from it
+#[derive(Debug, Clone)]
+pub struct Peer<Input, Address, Id = DefaultPlayerId> {
+    pub address: Address,
+    // Players whose inputs this peer sends. Inputs it sends for anyone else are ignored
+    pub players: Vec<Id>,
+    pub unacked_inputs: RedundantInputQueue<Input, Id>,
+    // Newest frame for which every earlier frame has been received from this peer
+    pub received_frame: Option<usize>,
+    // Frames received out of order past the received frame
+    pub pending_frames: BTreeSet<usize>,
+    pub time_sync: TimeSync
+}
+
+impl<Input: Clone, Address, Id: WirePlayerId> Peer<Input, Address, Id> {
+    pub fn new(address: Address, players: Vec<Id>, redundancy: usize) -> Peer<Input, Address, Id> {
+        Peer {
+            address,
+            players,
+            unacked_inputs: RedundantInputQueue::new(redundancy),
+            received_frame: None,
+            pending_frames: BTreeSet::new(),
+            time_sync: TimeSync::new()
+        }
+    }
+
+    // Whether a frame was already received, so any input for it is a resend
+    fn has_received(&self, frame: usize) -> bool {
+        self.received_frame.is_some_and(|received_frame| frame <= received_frame) || self.pending_frames.contains(&frame)
+    }
+
+    fn receive_frame(&mut self, frame: usize) {
+        if self.received_frame.is_some_and(|received_frame| frame <= received_frame) {
+            return;
+        }
+        self.pending_frames.insert(frame);
+
+        // Peers resend their oldest unacknowledged inputs first, so the first frame received is
+        // where their inputs start
+        let mut next_frame = match self.received_frame {
+            Some(received_frame) => received_frame + 1,
+            None => self.pending_frames.iter().next().copied().unwrap_or(frame)
+        };
+        while self.pending_frames.remove(&next_frame) {
+            self.received_frame = Some(next_frame);
+            next_frame += 1;
+        }
+    }
+}
+
+// Spectator watching the session, along with the first frame it still needs
+#[derive(Debug, Clone)]
+pub struct SpectatorPeer<Address> {
+    pub address: Address,
+    pub next_frame: usize
+}
+
+// Peer to peer rollback session. Local inputs are sent to every peer each poll until they are
+// acknowledged, and inputs received from peers are applied to the state manager
+pub struct P2PSession<Input, State, Address, Transport, Codec, Predictor = RepeatLastInput, Id = DefaultPlayerId>
+        where Input: Eq + Clone + Debug, State: SaveState + Debug, Predictor: InputPredictor<Input, Id>, Id: WirePlayerId {
+    pub manager: RollbackStateManager<Input, State, Predictor, Id>,
+    pub socket: Transport,
+    pub codec: Codec,
+    pub local_player: Id,
+    pub peers: Vec<Peer<Input, Address, Id>>,
+    pub spectators: Vec<SpectatorPeer<Address>>,
+    // Inputs of consecutive confirmed frames starting at the given frame, kept until every
+    // spectator has them
+    pub confirmed_inputs_start: usize,
+    pub confirmed_inputs: VecDeque<FrameInputs<Input, Id>>,
+    // Number of the oldest and of the newest unacknowledged inputs resent in every packet
+    pub redundancy: usize,
+    // Frames between adding a local input and the frame it applies to. Changes take effect
+    // gradually so no frame is left without an input or given two
+    pub input_delay: usize,
+    pub last_local_input: Option<(usize, Input)>,
+    // Number of polls so far. Round trip times are measured in polls, so they should happen about
+    // once a frame
+    pub tick: u64,
+    // Hold back frames automatically while running ahead of a peer
+    pub auto_time_sync: bool,
+    wait_frames: usize,
+    receive_buffer: Vec<u8>
+}
+
+impl<Input, State, Address, Transport, Codec, Predictor, Id> P2PSession<Input, State, Address, Transport, Codec, Predictor, Id>
+        where Input: Eq + Clone + Debug, State: SaveState + Debug, Address: Eq + Clone,
+              Transport: Socket<Address>, Codec: InputCodec<Input>, Predictor: InputPredictor<Input, Id>, Id: WirePlayerId {
+    pub fn new(manager: RollbackStateManager<Input, State, Predictor, Id>, socket: Transport, codec: Codec, local_player: Id) -> P2PSession<Input, State, Address, Transport, Codec, Predictor, Id> {
+        P2PSession {
+            manager,
+            socket,
+            codec,
+            local_player,
+            peers: Vec::new(),
+            spectators: Vec::new(),
+            confirmed_inputs_start: 0,
+            confirmed_inputs: VecDeque::new(),
+            redundancy: 16,
+            input_delay: 0,
+            last_local_input: None,
+            tick: 0,
+            auto_time_sync: false,
+            wait_frames: 0,
+            receive_buffer: vec![0; MAX_DATAGRAM_SIZE]
+        }
+    }
+
+    // Add a peer along with the players whose inputs it sends
+    pub fn add_peer(&mut self, address: Address, players: Vec<Id>) {
+        self.peers.push(Peer::new(address, players, self.redundancy));
+    }
+
+    // Send confirmed inputs to a spectator starting from the given frame, usually the frame of a
+    // confirmed snapshot it joined from. Frames are only sent once confirmed, so hosts with
+    // spectators should wait for confirmation to keep frames from leaving the rollback window first,
+    // and add players explicitly so frames aren't confirmed before a player's first input arrives
+    pub fn add_spectator(&mut self, address: Address, next_frame: usize) {
+        self.spectators.push(SpectatorPeer {
+            address,
+            next_frame
+        });
+    }
+
+    // Frame the next local input will be applied to. Local inputs always land on consecutive
+    // frames, so after the delay is lowered this stays ahead of the requested delay until it
+    // catches up
+    pub fn local_input_frame(&self) -> usize {
+        let scheduled_frame = self.manager.current_frame_index + 1 + self.input_delay;
+        match &self.last_local_input {
+            Some((last_frame, _)) => scheduled_frame.max(last_frame + 1),
+            None => scheduled_frame
+        }
+    }
+
+    // Record the local player's input for the next frame after the input delay and queue it for
+    // every peer. Should be called once per frame
+    pub fn add_local_input(&mut self, input: Input) -> Result<()> {
+        let scheduled_frame = self.manager.current_frame_index + 1 + self.input_delay;
+        let frame = self.local_input_frame();
+
+        if let Some((last_frame, last_input)) = self.last_local_input.clone() {
+            // The delay was lowered. Inputs repeating the previous one can be skipped without
+            // losing anything, which brings the local frames back toward the requested delay
+            if frame > scheduled_frame && last_input == input {
+                return Ok(());
+            }
+
+            // The delay was raised. Hold the previous input through the frames skipped over
+            for skipped_frame in last_frame + 1..frame {
+                self.send_local_input(skipped_frame, last_input.clone())?;
+            }
+        } else {
+            // Frames aren't confirmed until every earlier input has arrived, so the first input
+            // also covers the frames before it which peers expect an input for
+            for skipped_frame in self.manager.next_expected_frame(&self.local_player)..frame {
+                self.send_local_input(skipped_frame, input.clone())?;
+            }
+        }
+
+        self.send_local_input(frame, input.clone())?;
+        self.last_local_input = Some((frame, input));
+        Ok(())
+    }
+
+    fn send_local_input(&mut self, frame: usize, input: Input) -> Result<()> {
+        self.manager.handle_input(frame, self.local_player.clone(), input.clone())?;
+        for peer in self.peers.iter_mut() {
+            peer.unacked_inputs.push(frame, self.local_player.clone(), input.clone());
+            peer.time_sync.record_sent(frame, self.tick);
+        }
+        Ok(())
+    }
+
+    // Receive every waiting packet, apply the inputs it carries, then send unacknowledged inputs to
+    // every peer. Errors applying received inputs are returned after sending
+    pub fn poll(&mut self) -> Result<()> {
+        self.tick += 1;
+        let received = self.receive();
+        for peer in self.peers.iter_mut() {
+            peer.time_sync.sample_local(self.manager.current_frame_index, self.tick);
+        }
+        self.queue_confirmed_inputs();
+        // Failing to send to one address doesn't stop the rest from being sent
+        let sent = self.send();
+        let sent_to_spectators = self.send_to_spectators();
+        received.and(sent).and(sent_to_spectators)
+    }
+
+    // Apply every waiting packet. An input which can't be applied doesn't stop the rest, and the
+    // first such error is returned at the end
+    fn receive(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        while let Some((length, address)) = self.socket.receive_from(&mut self.receive_buffer)? {
+            // Packets from unknown addresses are dropped without decoding them
+            let spectator_index = self.spectators.iter().position(|spectator| spectator.address == address);
+            let peer_index = self.peers.iter().position(|peer| peer.address == address);
+            if spectator_index.is_none() && peer_index.is_none() {
+                continue;
+            }
+
+            let batch = match InputBatch::decode(&self.receive_buffer[..length], &self.codec) {
+                Ok(batch) => batch,
+                // Corrupt packets and packets from other wire versions are dropped like lost ones.
+                // Corrupt packets are covered by later resends
+                Err(_) => continue
+            };
+
+            // Spectators only send acknowledgements
+            if let Some(spectator_index) = spectator_index {
+                let spectator = &mut self.spectators[spectator_index];
+                if let Some(ack_frame) = batch.ack_frame {
+                    spectator.next_frame = spectator.next_frame.max(ack_frame + 1);
+                }
+                continue;
+            }
+
+            let peer_index = match peer_index {
+                Some(peer_index) => peer_index,
+                None => continue
+            };
+
+            let peer = &mut self.peers[peer_index];
+            if let Some(ack_frame) = batch.ack_frame {
+                peer.unacked_inputs.ack(ack_frame);
+                peer.time_sync.record_ack(ack_frame, self.tick);
+            }
+            peer.time_sync.record_remote(batch.frame, batch.frame_advantage, self.tick);
+
+            for (frame, id, input) in batch.inputs {
+                if !peer.players.contains(&id) {
+                    continue;
+                }
+                let resent = peer.has_received(frame);
+                peer.receive_frame(frame);
+                match self.manager.handle_input(frame, id, input) {
+                    Ok(()) => {},
+                    // Resent inputs which already left the rollback window
+                    Err(RollbackError::InputTooOld { .. }) if resent => {},
+                    Err(error) => {
+                        if result.is_ok() {
+                            result = Err(error);
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    // Send every peer its unacknowledged inputs. A peer which can't be sent to doesn't stop the
+    // others, and the first error is returned at the end
+    fn send(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        let mut buffer = Vec::new();
+        for peer in self.peers.iter() {
+            buffer.clear();
+            let mut batch = peer.unacked_inputs.batch(peer.received_frame);
+            batch.frame = self.manager.current_frame_index;
+            batch.frame_advantage = peer.time_sync.latest_local_frame_advantage();
+            batch.encode(&self.codec, &mut buffer);
+            let sent = self.socket.send_to(&buffer, &peer.address);
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        result
+    }
+
+    // Copy newly confirmed frames into the spectator queue, and forget frames which every spectator
+    // has. Frames still in the rollback window are kept so spectators can join from a snapshot
+    fn queue_confirmed_inputs(&mut self) {
+        let oldest_frame_index = self.manager.oldest_frame_index;
+        let mut next_frame = self.confirmed_inputs_start + self.confirmed_inputs.len();
+        if next_frame < oldest_frame_index {
+            // Frames which left the rollback window unconfirmed can't be sent anymore
+            self.confirmed_inputs.clear();
+            self.confirmed_inputs_start = oldest_frame_index;
+            next_frame = oldest_frame_index;
+        }
+
+        if let Some(confirmed_frame) = self.manager.confirmed_frame() {
+            for frame in next_frame..confirmed_frame + 1 {
+                self.confirmed_inputs.push_back(self.manager.get_frame_inputs(frame));
+            }
+        }
+
+        let needed_frame = self.spectators.iter()
+            .map(|spectator| spectator.next_frame)
+            .min()
+            .map_or(oldest_frame_index, |next_frame| next_frame.min(oldest_frame_index));
+        while self.confirmed_inputs_start < needed_frame && self.confirmed_inputs.pop_front().is_some() {
+            self.confirmed_inputs_start += 1;
+        }
+    }
+
+    fn send_to_spectators(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        let mut buffer = Vec::new();
+        let end_frame = self.confirmed_inputs_start + self.confirmed_inputs.len();
+        for spectator in self.spectators.iter() {
+            let first_frame = spectator.next_frame.max(self.confirmed_inputs_start);
+            if first_frame >= end_frame {
+                continue;
+            }
+
+            let mut confirmed_frames = ConfirmedFrames::new(first_frame);
+            confirmed_frames.frames.extend(self.confirmed_inputs.iter()
+                .skip(first_frame - self.confirmed_inputs_start)
+                .take(self.redundancy)
+                .cloned());
+
+            buffer.clear();
+            confirmed_frames.encode(&self.codec, &mut buffer);
+            let sent = self.socket.send_to(&buffer, &spectator.address);
+            if result.is_ok() {
+                result = sent;
+            }
+        }
+        result
+    }
+
+    // Whether to wait for or catch up with peers. Waiting is based on the furthest behind peer so
+    // nobody is left behind, which means skipping is only recommended when behind every peer
+    pub fn time_sync_recommendation(&self) -> TimeSyncRecommendation {
+        let frames_ahead = self.peers.iter()
+            .filter(|peer| peer.time_sync.is_settled())
+            .map(|peer| peer.time_sync.frames_ahead())
+            .max();
+        TimeSyncRecommendation::from_frames_ahead(frames_ahead.unwrap_or(0))
+    }
+
+    // Progress the state manager by a frame. With automatic time sync, this holds back without
+    // simulating anything while running ahead of a peer
+    pub fn advance_frame<F>(&mut self, update: F) -> Result<Progress<Id>>
+            where F: Fn(&FrameInputs<Input, Id>, State) -> State {
+        if let Some(progress) = self.hold_for_time_sync() {
+            return Ok(progress);
+        }
+        self.manager.progress_frame(update)
+    }
+
+    // Same as advance_frame, but the update function changes the state in place
+    pub fn advance_frame_mut<F>(&mut self, update: F) -> Result<Progress<Id>>
+            where F: FnMut(&FrameInputs<Input, Id>, &mut State) {
+        if let Some(progress) = self.hold_for_time_sync() {
+            return Ok(progress);
+        }
+        self.manager.progress_frame_mut(update)
+    }
+
+    // Same as advance_frame, but simulated by a game
+    pub fn advance_game<Game>(&mut self, game: &mut Game) -> Result<Progress<Id>>
+            where Game: RollbackGame<Input = Input, State = State, Id = Id> {
+        if let Some(progress) = self.hold_for_time_sync() {
+            return Ok(progress);
+        }
+        self.manager.progress_game(game)
+    }
+
+    fn hold_for_time_sync(&mut self) -> Option<Progress<Id>> {
+        if !self.auto_time_sync {
+            return None;
+        }
+
+        if self.wait_frames == 0 {
+            if let TimeSyncRecommendation::Wait(frames) = self.time_sync_recommendation() {
+                self.wait_frames = frames;
+                // Averages from before the wait would ask for it again
+                for peer in self.peers.iter_mut() {
+                    peer.time_sync.clear_samples();
+                }
+            }
+        }
+
+        if self.wait_frames > 0 {
+            let frames_ahead = self.wait_frames;
+            self.wait_frames -= 1;
+            return Some(Progress::HoldingBack {
+                frame: self.manager.current_frame_index + 1,
+                frames_ahead
+            });
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use crate::{InputStatus, NetworkConditions, SimulatedNetwork, SimulatedSocket, VarintCodec};
+    use crate::test_helpers::{add_input_once_per_frame, update};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    type TestSession = P2PSession<u8, u64, usize, SimulatedSocket, VarintCodec, RepeatLastInput, u32>;
+
+    #[test]
+    fn P2PSession_Loopback_PeersConverge() -> Result<()> {
+        let localhost = SocketAddr::from(([127, 0, 0, 1], 0));
+        let socket_a = bind_udp_socket(localhost)?;
+        let socket_b = bind_udp_socket(localhost)?;
+        let address_a = socket_a.local_addr().unwrap();
+        let address_b = socket_b.local_addr().unwrap();
+
+        let mut session_a = P2PSession::new(RollbackStateManager::new(0, 32), socket_a, VarintCodec, 1u32);
+        let mut session_b = P2PSession::new(RollbackStateManager::new(0, 32), socket_b, VarintCodec, 2u32);
+        session_a.add_peer(address_b, vec![session_b.local_player]);
+        session_b.add_peer(address_a, vec![session_a.local_player]);
+
+        for frame in 0..30u8 {
+            session_a.add_local_input(frame % 4)?;
+            session_b.add_local_input(frame % 3)?;
+            session_a.poll()?;
+            session_b.poll()?;
+            session_a.advance_frame(update)?;
+            session_b.advance_frame(update)?;
+        }
+
+        // Keep exchanging packets until every input has been acknowledged
+        let start = Instant::now();
+        while !(session_a.peers[0].unacked_inputs.is_empty() && session_b.peers[0].unacked_inputs.is_empty()) {
+            assert!(start.elapsed() < Duration::from_secs(5), "Inputs were never acknowledged");
+            session_a.poll()?;
+            session_b.poll()?;
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(session_a.manager.confirmed_frame(), Some(30));
+        assert_eq!(session_b.manager.confirmed_frame(), Some(30));
+        session_a.advance_frame(update)?;
+        session_b.advance_frame(update)?;
+        assert_eq!(session_a.manager.current_frame_state, session_b.manager.current_frame_state);
+
+        Ok(())
+    }
+
+    #[test]
+    fn P2PSession_IntegerPlayerSlots_PeersConverge() -> Result<()> {
+        let network = SimulatedNetwork::new(NetworkConditions { latency: 1, jitter: 1, loss: 0.1, duplication: 0.0, reordering: 0.0 }, 5);
+
+        let mut sessions: Vec<P2PSession<u8, u64, usize, SimulatedSocket, VarintCodec, RepeatLastInput, u8>> = (0..2u8)
+            .map(|slot| P2PSession::new(RollbackStateManager::new(0, 32), network.socket(), VarintCodec, slot))
+            .collect();
+        sessions[0].add_peer(1, vec![1]);
+        sessions[1].add_peer(0, vec![0]);
+
+        for frame in 0..30u8 {
+            for (slot, session) in sessions.iter_mut().enumerate() {
+                session.add_local_input(frame % (slot as u8 + 3))?;
+                session.poll()?;
+                session.advance_frame(update)?;
+            }
+            network.advance();
+        }
+        for _ in 0..20 {
+            for session in sessions.iter_mut() {
+                session.poll()?;
+            }
+            network.advance();
+        }
+
+        for session in sessions.iter_mut() {
+            assert_eq!(session.manager.confirmed_frame(), Some(30));
+            session.advance_frame(update)?;
+        }
+        assert_eq!(sessions[0].manager.current_frame_state, sessions[1].manager.current_frame_state);
+
+        Ok(())
+    }
+
+    // Socket which can't send to one address
+    struct UnreachableSocket {
+        socket: SimulatedSocket,
+        unreachable: usize
+    }
+
+    impl Socket<usize> for UnreachableSocket {
+        fn send_to(&mut self, bytes: &[u8], address: &usize) -> Result<()> {
+            if *address == self.unreachable {
+                return Err(RollbackError::Network { reason: "unreachable".to_string() });
+            }
+            self.socket.send_to(bytes, address)
+        }
+
+        fn receive_from(&mut self, buffer: &mut [u8]) -> Result<Option<(usize, usize)>> {
+            self.socket.receive_from(buffer)
+        }
+    }
+
+    #[test]
+    fn Poll_UnreachablePeer_StillSendsToOthers() -> Result<()> {
+        let network = SimulatedNetwork::new(NetworkConditions { latency: 1, jitter: 0, loss: 0.0, duplication: 0.0, reordering: 0.0 }, 3);
+        let socket = UnreachableSocket { socket: network.socket(), unreachable: 1 };
+        let mut session = P2PSession::new(RollbackStateManager::new(0, 8), socket, VarintCodec, 0u32);
+        let _unreachable_socket = network.socket();
+        let mut peer_socket = network.socket();
+        let mut spectator_socket = network.socket();
+        session.add_peer(1, vec![1]);
+        session.add_peer(peer_socket.address, vec![2]);
+        session.add_spectator(spectator_socket.address, 0);
+        session.add_local_input(4u8)?;
+        session.manager.handle_input(0, 1, 0)?;
+        session.manager.handle_input(0, 2, 0)?;
+
+        assert!(matches!(session.poll(), Err(RollbackError::Network { .. })));
+        network.advance();
+
+        let mut buffer = [0; MAX_DATAGRAM_SIZE];
+        let (length, _) = peer_socket.receive_from(&mut buffer)?.unwrap();
+        let batch: InputBatch<u8, u32> = InputBatch::decode(&buffer[..length], &VarintCodec)?;
+        assert!(batch.inputs.contains(&(0, 0, 4)));
+        assert!(spectator_socket.receive_from(&mut buffer)?.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn Poll_StrayPackets_DropsThem() -> Result<()> {
+        let network = SimulatedNetwork::new(NetworkConditions { latency: 1, jitter: 0, loss: 0.0, duplication: 0.0, reordering: 0.0 }, 3);
+        let mut session: P2PSession<u8, u64, usize, SimulatedSocket, VarintCodec, RepeatLastInput, u8> =
+            P2PSession::new(RollbackStateManager::new(0, 8), network.socket(), VarintCodec, 0);
+        let mut peer_socket = network.socket();
+        let mut stranger_socket = network.socket();
+        session.add_peer(peer_socket.address, vec![1]);
+
+        // A peer running another wire version, and a packet from an address which isn't a peer
+        peer_socket.send_to(&[99, 0, 0], &session.socket.address)?;
+        let mut buffer = Vec::new();
+        let mut batch = InputBatch::new(None);
+        batch.inputs.push((1, 1u8, 3u8));
+        batch.encode(&VarintCodec, &mut buffer);
+        stranger_socket.send_to(&buffer, &session.socket.address)?;
+        network.advance();
+
+        session.poll()?;
+        assert_eq!(session.peers[0].received_frame, None);
+        assert_eq!(session.manager.get_player_input(1, &1), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn Poll_PeerInputs_AppliesOnlyOwnPlayers() -> Result<()> {
+        let network = SimulatedNetwork::new(NetworkConditions { latency: 1, jitter: 0, loss: 0.0, duplication: 0.0, reordering: 0.0 }, 3);
+        let mut session: P2PSession<u8, u64, usize, SimulatedSocket, VarintCodec, RepeatLastInput, u8> =
+            P2PSession::new(RollbackStateManager::new(0, 4), network.socket(), VarintCodec, 0);
+        let mut peer_socket = network.socket();
+        session.add_peer(peer_socket.address, vec![1]);
+        let mut send_inputs = |inputs: Vec<(usize, u8, u8)>| -> Result<()> {
+            let mut buffer = Vec::new();
+            let mut batch = InputBatch::new(None);
+            batch.inputs = inputs;
+            batch.encode(&VarintCodec, &mut buffer);
+            peer_socket.send_to(&buffer, &0)?;
+            network.advance();
+            Ok(())
+        };
+
+        // Inputs claiming to be from the local player are ignored
+        session.add_local_input(1)?;
+        send_inputs(vec![(1, 0, 7), (1, 1, 2)])?;
+        session.poll()?;
+        assert_eq!(session.manager.get_player_input(1, &0), Some(1));
+        assert_eq!(session.manager.get_player_input(1, &1), Some(2));
+
+        for _ in 0..10 {
+            session.advance_frame(update)?;
+        }
+
+        // Resends of old frames are expected, but a first input which is already too old is not
+        send_inputs(vec![(1, 1, 2)])?;
+        session.poll()?;
+        send_inputs(vec![(2, 1, 2)])?;
+        assert!(matches!(session.poll(), Err(RollbackError::InputTooOld { input_frame: 2, .. })));
+        assert_eq!(session.peers[0].received_frame, Some(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn AddLocalInput_ChangingInputDelay_KeepsFramesContiguous() -> Result<()> {
+        let network = SimulatedNetwork::new(NetworkConditions::default(), 0);
+        let mut session = P2PSession::new(RollbackStateManager::new(0, 32), network.socket(), VarintCodec, 1u32);
+        session.add_peer(network.socket().address, vec![2]);
+        session.input_delay = 2;
+
+        let mut local_frames = Vec::new();
+        let mut add_input = |session: &mut TestSession, input: u8| -> Result<()> {
+            session.add_local_input(input)?;
+            local_frames.push(session.last_local_input.as_ref().map(|(frame, _)| *frame));
+            session.advance_frame(update)?;
+            Ok(())
+        };
+
+        add_input(&mut session, 1)?;
+        assert_eq!(session.last_local_input, Some((3, 1)));
+        add_input(&mut session, 2)?;
+
+        // Raising the delay holds input 2 through frames 5 and 6 before input 3 lands on frame 7
+        session.input_delay = 4;
+        add_input(&mut session, 3)?;
+        assert_eq!(session.last_local_input, Some((7, 3)));
+        assert_eq!(session.manager.get_frame_inputs(6).get(&session.local_player), Some(&2));
+
+        // Lowering the delay only skips repeated inputs
+        session.input_delay = 1;
+        add_input(&mut session, 4)?;
+        add_input(&mut session, 4)?;
+        add_input(&mut session, 4)?;
+        add_input(&mut session, 5)?;
+        assert_eq!(session.last_local_input, Some((9, 5)));
+        assert_eq!(session.local_input_frame(), session.manager.current_frame_index + 3);
+        add_input(&mut session, 5)?;
+        assert_eq!(session.local_input_frame(), session.manager.current_frame_index + 2);
+
+        // Every frame has exactly one queued input, with the first input covering the frames
+        // before it
+        let queued: Vec<usize> = session.peers[0].unacked_inputs.batch(None).inputs.iter().map(|(frame, _, _)| *frame).collect();
+        assert_eq!(queued, (0..10).collect::<Vec<usize>>());
+        assert_eq!(session.manager.get_frame_inputs(0).status(&session.local_player), Some(InputStatus::Confirmed));
+        assert_eq!(local_frames, vec![Some(3), Some(4), Some(7), Some(8), Some(8), Some(8), Some(9), Some(9)]);
+
+        Ok(())
+    }
+
+    // Session 0 starts advancing immediately and session 1 ten ticks later, then both advance a
+    // frame every tick they're allowed to. Returns the sessions and how many frames session 0 held
+    // back for
+    fn run_offset_sessions(auto_time_sync: bool, ticks: u64) -> Result<(Vec<TestSession>, usize)> {
+        let conditions = NetworkConditions { latency: 2, jitter: 1, ..NetworkConditions::default() };
+        let network = SimulatedNetwork::new(conditions, 99);
+        let mut sessions = Vec::new();
+        for player in 1..3u32 {
+            let mut session = P2PSession::new(RollbackStateManager::new(0, 64), network.socket(), VarintCodec, player);
+            session.auto_time_sync = auto_time_sync;
+            sessions.push(session);
+        }
+        let players = [sessions[0].local_player, sessions[1].local_player];
+        sessions[0].add_peer(1, vec![players[1]]);
+        sessions[1].add_peer(0, vec![players[0]]);
+
+        let mut held_frames = 0;
+        let mut input_frames = [None; 2];
+        for tick in 0..ticks {
+            for (index, session) in sessions.iter_mut().enumerate() {
+                add_input_once_per_frame(session, &mut input_frames[index], usize::MAX, (tick % 3) as u8)?;
+
+                session.poll()?;
+                if index == 1 && tick < 10 {
+                    continue;
+                }
+                if let Progress::HoldingBack { .. } = session.advance_frame(update)? {
+                    assert_eq!(index, 0);
+                    held_frames += 1;
+                }
+            }
+            network.advance();
+        }
+
+        Ok((sessions, held_frames))
+    }
+
+    #[test]
+    fn TimeSyncRecommendation_OffsetPeers_RecommendsWaitAndSkip() -> Result<()> {
+        let (sessions, held_frames) = run_offset_sessions(false, 100)?;
+        assert_eq!(held_frames, 0);
+        assert_eq!(sessions[0].manager.current_frame_index, sessions[1].manager.current_frame_index + 10);
+
+        let round_trip_ticks = sessions[0].peers[0].time_sync.round_trip_ticks.unwrap();
+        assert!((4.0..=8.0).contains(&round_trip_ticks), "Unexpected round trip of {}", round_trip_ticks);
+        assert!(matches!(sessions[0].time_sync_recommendation(), TimeSyncRecommendation::Wait(9..=11)));
+        assert!(matches!(sessions[1].time_sync_recommendation(), TimeSyncRecommendation::Skip(9..=11)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn AdvanceFrame_AutoTimeSync_WaitsForPeer() -> Result<()> {
+        let (sessions, held_frames) = run_offset_sessions(true, 200)?;
+        assert!(held_frames >= 8, "Only held back {} frames", held_frames);
+
+        let frames_ahead = sessions[0].manager.current_frame_index as i64 - sessions[1].manager.current_frame_index as i64;
+        assert!(frames_ahead.abs() < crate::MIN_FRAME_ADVANTAGE, "Sessions ended {} frames apart", frames_ahead);
+        assert_eq!(sessions[0].time_sync_recommendation(), TimeSyncRecommendation::InSync);
+
+        Ok(())
+    }
+
+    #[test]
+    fn Peer_ReceiveFrame_AcksContiguousFrames() {
+        let mut peer: Peer<u8, ()> = Peer::new((), Vec::new(), 4);
+        peer.receive_frame(1);
+        assert_eq!(peer.received_frame, Some(1));
+
+        peer.receive_frame(3);
+        peer.receive_frame(4);
+        assert_eq!(peer.received_frame, Some(1));
+        peer.receive_frame(2);
+        assert_eq!(peer.received_frame, Some(4));
+        assert!(peer.pending_frames.is_empty());
+
+        peer.receive_frame(3);
+        assert_eq!(peer.received_frame, Some(4));
+    }
+}
@@ -0,0 +1,309 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::{Result, Socket};
+
+// Small deterministic random number generator so simulated networks can be replayed from a seed
+#[derive(Debug, Clone)]
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> SplitMix64 {
+        SplitMix64(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut value = self.0;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        value ^ (value >> 31)
+    }
+
+    // Uniform value in 0..=max
+    pub fn next_below_or_equal(&mut self, max: u64) -> u64 {
+        if max == u64::MAX {
+            self.next_u64()
+        } else {
+            self.next_u64() % (max + 1)
+        }
+    }
+
+    // True with the given probability
+    pub fn chance(&mut self, probability: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+// How badly the simulated network treats packets. Delays are measured in network ticks
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NetworkConditions {
+    pub latency: u64,
+    // Extra delay up to this many ticks added to each packet
+    pub jitter: u64,
+    // Chance of a packet being dropped
+    pub loss: f64,
+    // Chance of a packet being delivered twice
+    pub duplication: f64,
+    // Chance of a packet being held back long enough to arrive after later packets
+    pub reordering: f64
+}
+
+#[derive(Debug)]
+struct InFlightPacket {
+    deliver_at: u64,
+    from: usize,
+    to: usize,
+    bytes: Vec<u8>
+}
+
+#[derive(Debug)]
+struct NetworkState {
+    conditions: NetworkConditions,
+    random: SplitMix64,
+    tick: u64,
+    in_flight: Vec<InFlightPacket>,
+    inboxes: Vec<VecDeque<(usize, Vec<u8>)>>
+}
+
+impl NetworkState {
+    fn delay(&mut self) -> u64 {
+        let mut delay = self.conditions.latency + self.random.next_below_or_equal(self.conditions.jitter);
+        if self.random.chance(self.conditions.reordering) {
+            delay += 1 + self.random.next_below_or_equal(self.conditions.latency + self.conditions.jitter);
+        }
+        delay
+    }
+
+    fn send(&mut self, from: usize, to: usize, bytes: &[u8]) {
+        if to >= self.inboxes.len() || self.random.chance(self.conditions.loss) {
+            return;
+        }
+
+        let copies = if self.random.chance(self.conditions.duplication) { 2 } else { 1 };
+        for _ in 0..copies {
+            let deliver_at = self.tick + self.delay();
+            self.in_flight.push(InFlightPacket {
+                deliver_at,
+                from,
+                to,
+                bytes: bytes.to_vec()
+            });
+        }
+    }
+}
+
+// Deterministic in memory network connecting any number of simulated sockets. Packets are only
+// delivered when the network is advanced, so tests fully control timing
+#[derive(Debug, Clone)]
+pub struct SimulatedNetwork {
+    state: Rc<RefCell<NetworkState>>
+}
+
+impl SimulatedNetwork {
+    pub fn new(conditions: NetworkConditions, seed: u64) -> SimulatedNetwork {
+        SimulatedNetwork {
+            state: Rc::new(RefCell::new(NetworkState {
+                conditions,
+                random: SplitMix64::new(seed),
+                tick: 0,
+                in_flight: Vec::new(),
+                inboxes: Vec::new()
+            }))
+        }
+    }
+
+    pub fn set_conditions(&self, conditions: NetworkConditions) {
+        self.state.borrow_mut().conditions = conditions;
+    }
+
+    // Create a socket attached to this network. Sockets are addressed by the order they were
+    // created in
+    pub fn socket(&self) -> SimulatedSocket {
+        let mut state = self.state.borrow_mut();
+        state.inboxes.push(VecDeque::new());
+        SimulatedSocket {
+            network: self.state.clone(),
+            address: state.inboxes.len() - 1
+        }
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.state.borrow().tick
+    }
+
+    pub fn packets_in_flight(&self) -> usize {
+        self.state.borrow().in_flight.len()
+    }
+
+    // Move time forward one tick and deliver every packet which is due
+    pub fn advance(&self) {
+        let mut state = self.state.borrow_mut();
+        state.tick += 1;
+        let tick = state.tick;
+
+        let (mut delivered, in_flight): (Vec<InFlightPacket>, Vec<InFlightPacket>) = state.in_flight.drain(..)
+            .partition(|packet| packet.deliver_at <= tick);
+        state.in_flight = in_flight;
+
+        // Packets due on the same tick arrive in the order they were scheduled for
+        delivered.sort_by_key(|packet| packet.deliver_at);
+        for packet in delivered {
+            state.inboxes[packet.to].push_back((packet.from, packet.bytes));
+        }
+    }
+}
+
+// Endpoint on a simulated network
+#[derive(Debug, Clone)]
+pub struct SimulatedSocket {
+    network: Rc<RefCell<NetworkState>>,
+    pub address: usize
+}
+
+impl Socket<usize> for SimulatedSocket {
+    fn send_to(&mut self, bytes: &[u8], address: &usize) -> Result<()> {
+        self.network.borrow_mut().send(self.address, *address, bytes);
+        Ok(())
+    }
+
+    fn receive_from(&mut self, buffer: &mut [u8]) -> Result<Option<(usize, usize)>> {
+        let packet = self.network.borrow_mut().inboxes[self.address].pop_front();
+        Ok(packet.map(|(from, bytes)| {
+            // Oversized packets are truncated like real datagrams
+            let length = bytes.len().min(buffer.len());
+            buffer[..length].copy_from_slice(&bytes[..length]);
+            (length, from)
+        }))
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use crate::{P2PSession, RollbackStateManager, VarintCodec};
+    use crate::test_helpers::{add_input_once_per_frame, update};
+
+    #[test]
+    fn SimulatedNetwork_SameSeed_SameDeliveries() -> Result<()> {
+        let conditions = NetworkConditions { latency: 2, jitter: 3, loss: 0.2, duplication: 0.2, reordering: 0.2 };
+        let mut deliveries = Vec::new();
+        for _ in 0..2 {
+            let network = SimulatedNetwork::new(conditions, 7);
+            let mut sender = network.socket();
+            let mut receiver = network.socket();
+
+            let mut received = Vec::new();
+            let mut buffer = [0; 8];
+            for packet in 0..50u8 {
+                sender.send_to(&[packet], &receiver.address)?;
+                network.advance();
+                while let Some((length, from)) = receiver.receive_from(&mut buffer)? {
+                    assert_eq!((length, from), (1, sender.address));
+                    received.push(buffer[0]);
+                }
+            }
+            deliveries.push(received);
+        }
+
+        assert_eq!(deliveries[0], deliveries[1]);
+        let mut sorted = deliveries[0].clone();
+        sorted.sort();
+        assert_ne!(deliveries[0], sorted, "Expected some packets to be reordered");
+        sorted.dedup();
+        assert!(sorted.len() < 50, "Expected some packets to be lost");
+        assert!(deliveries[0].len() > sorted.len(), "Expected some packets to be duplicated");
+
+        Ok(())
+    }
+
+    #[test]
+    fn P2PSession_SimulatedNetwork_PeersConverge() -> Result<()> {
+        let conditions = NetworkConditions { latency: 3, jitter: 4, loss: 0.15, duplication: 0.1, reordering: 0.1 };
+        let network = SimulatedNetwork::new(conditions, 1234);
+
+        let mut sessions = Vec::new();
+        for player in 0..3u32 {
+            let mut manager = RollbackStateManager::new(0u64, 16);
+            manager.wait_for_confirmation = true;
+            sessions.push(P2PSession::new(manager, network.socket(), VarintCodec, player));
+        }
+        for index in 0..sessions.len() {
+            for peer in 0..sessions.len() {
+                if peer != index {
+                    let players = vec![sessions[peer].local_player];
+                    sessions[index].add_peer(peer, players);
+                }
+            }
+        }
+
+        let target_frame = 120;
+        let mut input_frames = vec![None; sessions.len()];
+        for tick in 0..2000u64 {
+            for (index, session) in sessions.iter_mut().enumerate() {
+                add_input_once_per_frame(session, &mut input_frames[index], target_frame, ((tick + index as u64) % 5) as u8)?;
+
+                session.poll()?;
+                if session.manager.current_frame_index < target_frame {
+                    session.advance_frame(update)?;
+                }
+            }
+            network.advance();
+
+            let finished = sessions.iter().all(|session| {
+                session.manager.current_frame_index == target_frame
+                    && session.manager.confirmed_frame() == Some(target_frame)
+                    && session.peers.iter().all(|peer| peer.unacked_inputs.is_empty())
+            });
+            if finished {
+                break;
+            }
+        }
+
+        for session in sessions.iter_mut() {
+            assert_eq!(session.manager.confirmed_frame(), Some(target_frame));
+            session.advance_frame(update)?;
+        }
+        let final_state = sessions[0].manager.current_frame_state;
+        assert!(sessions.iter().all(|session| session.manager.current_frame_state == final_state));
+
+        Ok(())
+    }
+
+    #[test]
+    fn P2PSession_RoundTripLongerThanRedundancy_KeepsUp() -> Result<()> {
+        // Acks take 24 ticks to come back, longer than the 16 inputs each batch resends
+        let conditions = NetworkConditions { latency: 12, jitter: 0, loss: 0.0, duplication: 0.0, reordering: 0.0 };
+        let network = SimulatedNetwork::new(conditions, 99);
+
+        let mut sessions = Vec::new();
+        for player in 0..2u32 {
+            sessions.push(P2PSession::new(RollbackStateManager::new(0u64, 64), network.socket(), VarintCodec, player));
+        }
+        for index in 0..2 {
+            let players = vec![sessions[1 - index].local_player];
+            sessions[index].add_peer(1 - index, players);
+        }
+
+        for tick in 0..400u64 {
+            for session in sessions.iter_mut() {
+                session.add_local_input((tick % 3) as u8)?;
+                session.poll()?;
+                session.advance_frame(update)?;
+            }
+            network.advance();
+        }
+
+        // Each peer's inputs arrive a network latency after they're sent, and only the inputs of
+        // the last round trip are still waiting for their ack
+        for session in sessions.iter() {
+            let current_frame = session.manager.current_frame_index;
+            assert!(session.manager.confirmed_frame().is_some_and(|confirmed_frame| confirmed_frame + 16 > current_frame));
+            assert!(session.peers[0].unacked_inputs.len() <= 2 * 12 + 2);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// Fixed capacity ring buffer of saved states, ordered from oldest frame to newest frame
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SnapshotBuffer<State> {
+    slots: Vec<Option<(usize, State)>>,
+    start: usize,
+    len: usize
+}
+
+impl<State> SnapshotBuffer<State> {
+    pub fn new(capacity: usize) -> SnapshotBuffer<State> {
+        let capacity = capacity.max(1);
+        let mut slots = Vec::with_capacity(capacity);
+        slots.resize_with(capacity, || None);
+
+        SnapshotBuffer {
+            slots,
+            start: 0,
+            len: 0
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn slot_index(&self, offset: usize) -> usize {
+        (self.start + offset) % self.slots.len()
+    }
+
+    fn get(&self, offset: usize) -> Option<&(usize, State)> {
+        self.slots[self.slot_index(offset)].as_ref()
+    }
+
+    // Save the state for a frame newer than every stored frame. If the buffer is full, the oldest
+    // snapshot is overwritten
+    pub fn push(&mut self, frame: usize, state: State) {
+        if self.len == self.slots.len() {
+            self.pop_oldest();
+        }
+
+        let index = self.slot_index(self.len);
+        self.slots[index] = Some((frame, state));
+        self.len += 1;
+    }
+
+    pub fn oldest_frame(&self) -> Option<usize> {
+        if self.is_empty() {
+            None
+        } else {
+            self.get(0).map(|(frame, _)| *frame)
+        }
+    }
+
+    pub fn newest_frame(&self) -> Option<usize> {
+        if self.is_empty() {
+            None
+        } else {
+            self.get(self.len - 1).map(|(frame, _)| *frame)
+        }
+    }
+
+    pub fn pop_oldest(&mut self) -> Option<(usize, State)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let snapshot = self.slots[self.start].take();
+        self.start = self.slot_index(1);
+        self.len -= 1;
+        snapshot
+    }
+
+    // Newest snapshot taken strictly before the given frame
+    pub fn latest_before(&self, frame: usize) -> Option<(usize, &State)> {
+        (0..self.len).rev()
+            .filter_map(|offset| self.get(offset))
+            .find(|(snapshot_frame, _)| *snapshot_frame < frame)
+            .map(|(snapshot_frame, state)| (*snapshot_frame, state))
+    }
+
+    // Drop every snapshot for the given frame or later
+    pub fn discard_from(&mut self, frame: usize) {
+        while let Some(newest_frame) = self.newest_frame() {
+            if newest_frame < frame {
+                break;
+            }
+
+            let index = self.slot_index(self.len - 1);
+            self.slots[index] = None;
+            self.len -= 1;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.discard_from(0);
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn Push_PastCapacity_OverwritesOldest() {
+        let mut snapshots = SnapshotBuffer::new(3);
+        for frame in 0..5 {
+            snapshots.push(frame, frame * 10);
+        }
+
+        assert_eq!(snapshots.len(), 3);
+        assert_eq!(snapshots.oldest_frame(), Some(2));
+        assert_eq!(snapshots.newest_frame(), Some(4));
+        assert_eq!(snapshots.pop_oldest(), Some((2, 20)));
+    }
+
+    #[test]
+    fn LatestBefore_DiscardFrom_FindsValidSnapshots() {
+        let mut snapshots = SnapshotBuffer::new(4);
+        for frame in (0..16).step_by(4) {
+            snapshots.push(frame, frame);
+        }
+
+        assert_eq!(snapshots.latest_before(9), Some((8, &8)));
+        assert_eq!(snapshots.latest_before(8), Some((4, &4)));
+        assert_eq!(snapshots.latest_before(0), None);
+
+        snapshots.discard_from(5);
+        assert_eq!(snapshots.newest_frame(), Some(4));
+        assert_eq!(snapshots.latest_before(100), Some((4, &4)));
+
+        // Wrapping around the end of the buffer keeps frames ordered
+        snapshots.push(5, 5);
+        snapshots.push(6, 6);
+        snapshots.push(7, 7);
+        assert_eq!(snapshots.oldest_frame(), Some(4));
+        assert_eq!(snapshots.latest_before(7), Some((6, &6)));
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{ConfirmedFrames, DefaultPlayerId, FrameInputs, InputBatch, InputCodec, PlayerId, Result, RollbackError, SaveState, Socket, WirePlayerId, MAX_DATAGRAM_SIZE};
+
+// Follows a game using only confirmed inputs, so it never predicts or rolls back. Frames are
+// simulated a buffering delay behind the newest received frame to ride out network jitter, and
+// several frames are simulated at once to catch up after falling behind
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound(
+    serialize = "Input: Serialize, State: Serialize, Id: Serialize",
+    deserialize = "Input: Deserialize<'de>, State: Deserialize<'de>, Id: Deserialize<'de>"
+)))]
+pub struct Spectator<Input, State, Id: PlayerId = DefaultPlayerId> {
+    // First frame which hasn't been simulated, and the state before it
+    pub next_frame: usize,
+    pub state: State,
+    // Confirmed inputs received for frames which haven't been simulated yet
+    pub confirmed_inputs: BTreeMap<usize, FrameInputs<Input, Id>>,
+    // Frames to have buffered before starting to simulate, or resuming after running out
+    pub buffer_frames: usize,
+    // Most frames simulated in a single advance while catching up
+    pub max_catch_up_frames: usize,
+    buffering: bool
+}
+
+impl<Input, State: SaveState, Id: PlayerId> Spectator<Input, State, Id> {
+    // Start from the state before the given frame, such as a host's confirmed snapshot
+    pub fn new(next_frame: usize, state: State) -> Spectator<Input, State, Id> {
+        Spectator {
+            next_frame,
+            state,
+            confirmed_inputs: BTreeMap::new(),
+            buffer_frames: 3,
+            max_catch_up_frames: 4,
+            buffering: true
+        }
+    }
+
+    pub fn handle_frame(&mut self, frame: usize, inputs: FrameInputs<Input, Id>) {
+        if frame >= self.next_frame {
+            self.confirmed_inputs.insert(frame, inputs);
+        }
+    }
+
+    // Number of consecutive frames received from the next frame onward
+    pub fn buffered_frames(&self) -> usize {
+        self.confirmed_inputs.keys()
+            .zip(self.next_frame..)
+            .take_while(|(frame, expected_frame)| **frame == *expected_frame)
+            .count()
+    }
+
+    // Newest frame for which every earlier frame has been received
+    pub fn received_frame(&self) -> Option<usize> {
+        (self.next_frame + self.buffered_frames()).checked_sub(1)
+    }
+
+    // Simulate the frames due this tick and return how many were simulated. A frame is simulated
+    // every tick once enough are buffered, with extra frames when more than the buffer is waiting
+    pub fn advance<F>(&mut self, update: F) -> usize
+            where F: Fn(&FrameInputs<Input, Id>, State) -> State {
+        let buffered_frames = self.buffered_frames();
+        if buffered_frames == 0 {
+            self.buffering = true;
+            return 0;
+        }
+        if self.buffering {
+            if buffered_frames < self.buffer_frames {
+                return 0;
+            }
+            self.buffering = false;
+        }
+
+        let frames = buffered_frames.saturating_sub(self.buffer_frames).clamp(1, self.max_catch_up_frames.max(1));
+        for _ in 0..frames {
+            if let Some(inputs) = self.confirmed_inputs.remove(&self.next_frame) {
+                self.state = update(&inputs, self.state.save_state());
+                self.next_frame += 1;
+            }
+        }
+        frames
+    }
+}
+
+// Spectator receiving confirmed inputs from a host session over a socket
+pub struct SpectatorSession<Input, State, Address, Transport, Codec, Id: PlayerId = DefaultPlayerId> {
+    pub spectator: Spectator<Input, State, Id>,
+    pub socket: Transport,
+    pub codec: Codec,
+    pub host: Address,
+    receive_buffer: Vec<u8>
+}
+
+impl<Input, State, Address, Transport, Codec, Id> SpectatorSession<Input, State, Address, Transport, Codec, Id>
+        where Input: Clone, State: SaveState, Address: Eq, Transport: Socket<Address>, Codec: InputCodec<Input>, Id: WirePlayerId {
+    pub fn new(spectator: Spectator<Input, State, Id>, socket: Transport, codec: Codec, host: Address) -> SpectatorSession<Input, State, Address, Transport, Codec, Id> {
+        SpectatorSession {
+            spectator,
+            socket,
+            codec,
+            host,
+            receive_buffer: vec![0; MAX_DATAGRAM_SIZE]
+        }
+    }
+
+    // Receive every waiting packet from the host, then acknowledge the frames received so far
+    pub fn poll(&mut self) -> Result<()> {
+        while let Some((length, address)) = self.socket.receive_from(&mut self.receive_buffer)? {
+            if address != self.host {
+                continue;
+            }
+            let confirmed_frames = match ConfirmedFrames::decode(&self.receive_buffer[..length], &self.codec) {
+                Ok(confirmed_frames) => confirmed_frames,
+                Err(RollbackError::MalformedMessage { .. }) => continue,
+                Err(error) => return Err(error)
+            };
+
+            for (frame, inputs) in (confirmed_frames.first_frame..).zip(confirmed_frames.frames) {
+                self.spectator.handle_frame(frame, inputs);
+            }
+        }
+
+        let mut buffer = Vec::new();
+        InputBatch::<Input, Id>::new(self.spectator.received_frame()).encode(&self.codec, &mut buffer);
+        self.socket.send_to(&buffer, &self.host)
+    }
+
+    // Simulate the frames due this tick, returning how many were simulated
+    pub fn advance_frame<F>(&mut self, update: F) -> usize
+            where F: Fn(&FrameInputs<Input, Id>, State) -> State {
+        self.spectator.advance(update)
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use crate::{NetworkConditions, P2PSession, RollbackStateManager, SimulatedNetwork, VarintCodec};
+    use crate::test_helpers::{add_input_once_per_frame, update};
+
+    #[test]
+    fn Advance_BufferedFrames_DelaysThenCatchesUp() {
+        let id = 1u32;
+        let mut spectator = Spectator::new(5, 0u64);
+        spectator.buffer_frames = 2;
+        spectator.max_catch_up_frames = 3;
+        let frame_inputs = |input: u8| -> FrameInputs<u8, u32> { vec![(id, input)].into_iter().collect() };
+
+        // Nothing runs until the buffer fills, and frames after a gap aren't counted
+        spectator.handle_frame(5, frame_inputs(1));
+        spectator.handle_frame(7, frame_inputs(3));
+        assert_eq!(spectator.advance(update), 0);
+        spectator.handle_frame(6, frame_inputs(2));
+        assert_eq!(spectator.received_frame(), Some(7));
+        assert_eq!(spectator.advance(update), 1);
+        assert_eq!(spectator.next_frame, 6);
+
+        // Falling behind runs extra frames, up to the catch up limit
+        for frame in 8..14 {
+            spectator.handle_frame(frame, frame_inputs(frame as u8));
+        }
+        assert_eq!(spectator.advance(update), 3);
+        assert_eq!(spectator.advance(update), 3);
+        assert_eq!(spectator.advance(update), 1);
+        assert_eq!(spectator.next_frame, 13);
+
+        let expected = (5..13u8).fold(0, |state, frame| update(&frame_inputs(if frame < 8 { frame - 4 } else { frame }), state));
+        assert_eq!(spectator.state, expected);
+    }
+
+    #[test]
+    fn SpectatorSession_SimulatedNetwork_MatchesHost() -> Result<()> {
+        let conditions = NetworkConditions { latency: 2, jitter: 2, loss: 0.1, duplication: 0.05, reordering: 0.05 };
+        let network = SimulatedNetwork::new(conditions, 17);
+
+        // Players are registered up front so frames aren't confirmed before hearing from everyone
+        let players = [1u32, 2];
+        let mut sessions = Vec::new();
+        for player in players.iter() {
+            let mut manager = RollbackStateManager::new(0u64, 16);
+            manager.wait_for_confirmation = true;
+            for id in players.iter() {
+                manager.add_player(*id, 0)?;
+            }
+            sessions.push(P2PSession::new(manager, network.socket(), VarintCodec, *player));
+        }
+        sessions[0].add_peer(1, vec![players[1]]);
+        sessions[1].add_peer(0, vec![players[0]]);
+
+        let spectator_socket = network.socket();
+        sessions[0].add_spectator(spectator_socket.address, 0);
+        let mut spectators = vec![SpectatorSession::new(Spectator::<u8, u64, u32>::new(0, 0), spectator_socket, VarintCodec, 0)];
+
+        let target_frame = 80;
+        let mut input_frames = [None; 2];
+        for tick in 0..1000u64 {
+            for (index, session) in sessions.iter_mut().enumerate() {
+                add_input_once_per_frame(session, &mut input_frames[index], target_frame, ((tick + index as u64) % 4) as u8)?;
+
+                session.poll()?;
+                if session.manager.current_frame_index < target_frame {
+                    session.advance_frame(update)?;
+                }
+            }
+
+            // A second spectator joins part way through from the host's confirmed snapshot
+            if tick == 40 {
+                let snapshot = sessions[0].manager.confirmed_snapshot();
+                #[cfg(feature = "serde")]
+                let snapshot: (usize, u64) = serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+                assert!(snapshot.0 > 0);
+
+                let socket = network.socket();
+                sessions[0].add_spectator(socket.address, snapshot.0);
+                spectators.push(SpectatorSession::new(Spectator::new(snapshot.0, snapshot.1), socket, VarintCodec, 0));
+            }
+
+            for spectator in spectators.iter_mut() {
+                spectator.poll()?;
+                spectator.advance_frame(update);
+            }
+            network.advance();
+
+            if spectators.iter().all(|spectator| spectator.spectator.next_frame > target_frame) {
+                break;
+            }
+        }
+
+        // Progress once more so the hosts simulate the final frame with every input, then compare
+        // against the confirmed state after it
+        for session in sessions.iter_mut() {
+            session.advance_frame(update)?;
+        }
+        let (host_frame, host_state) = sessions[0].manager.confirmed_snapshot();
+        assert_eq!(host_frame, target_frame + 1);
+        assert_eq!(sessions[1].manager.confirmed_snapshot(), (host_frame, host_state));
+        for spectator in spectators.iter() {
+            assert_eq!(spectator.spectator.next_frame, target_frame + 1);
+            assert_eq!(spectator.spectator.state, host_state);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use core::fmt::Debug;
+use crate::{Checksum, DefaultPlayerId, FrameInputs, InputPredictor, PlayerId, Progress, RepeatLastInput, Result, RollbackError, RollbackGame, RollbackStateManager, SaveState};
+
+// Forces a rollback on every frame to catch non-deterministic update functions. After each
+// progressed frame, the last few frames are simulated again from saved states and their checksums
+// compared with the first simulation
+pub struct SyncTestSession<Input, State, Predictor = RepeatLastInput, Id = DefaultPlayerId>
+        where Input: Eq + Clone + Debug, State: SaveState + Debug, Predictor: InputPredictor<Input, Id>, Id: PlayerId {
+    pub manager: RollbackStateManager<Input, State, Predictor, Id>,
+    // Frames rolled back and simulated again after every progressed frame. Limited by the rollback
+    // window
+    pub check_distance: usize
+}
+
+impl<Input, State, Predictor, Id> SyncTestSession<Input, State, Predictor, Id>
+        where Input: Eq + Clone + Debug, State: SaveState + Debug + Checksum, Predictor: InputPredictor<Input, Id>, Id: PlayerId {
+    pub fn new(mut manager: RollbackStateManager<Input, State, Predictor, Id>, check_distance: usize) -> SyncTestSession<Input, State, Predictor, Id> {
+        manager.enable_checksums();
+        SyncTestSession {
+            manager,
+            check_distance
+        }
+    }
+
+    // Progress the state manager by a frame, then roll back and simulate the checked frames again.
+    // Returns an error for the first frame whose checksum changed
+    pub fn progress_frame<F>(&mut self, update: F) -> Result<Progress<Id>>
+            where F: Fn(&FrameInputs<Input, Id>, State) -> State {
+        let progress = self.manager.progress_frame(&update)?;
+        self.check_frames(progress, |manager, frame| {
+            manager.resimulate_from(frame, &update);
+        })
+    }
+
+    // Same as progress_frame, but the update function changes the state in place
+    pub fn progress_frame_mut<F>(&mut self, mut update: F) -> Result<Progress<Id>>
+            where F: FnMut(&FrameInputs<Input, Id>, &mut State) {
+        let progress = self.manager.progress_frame_mut(&mut update)?;
+        self.check_frames(progress, |manager, frame| {
+            manager.resimulate_from_mut(frame, &mut update);
+        })
+    }
+
+    // Same as progress_frame, but simulated by a game
+    pub fn progress_game<Game>(&mut self, game: &mut Game) -> Result<Progress<Id>>
+            where Game: RollbackGame<Input = Input, State = State, Id = Id> {
+        let progress = self.manager.progress_game(game)?;
+        self.check_frames(progress, |manager, frame| {
+            manager.resimulate_game(frame, game);
+        })
+    }
+
+    // Simulate the checked frames again from the given frame onward with the resimulate function,
+    // and compare their checksums
+    fn check_frames<R>(&mut self, progress: Progress<Id>, resimulate: R) -> Result<Progress<Id>>
+            where R: FnOnce(&mut RollbackStateManager<Input, State, Predictor, Id>, usize) {
+        if progress.is_waiting() {
+            return Ok(progress);
+        }
+
+        let current_frame_index = self.manager.current_frame_index;
+        let first_checked_frame = (current_frame_index + 1).saturating_sub(self.check_distance);
+        let original_checksums: Vec<(usize, u64)> = (first_checked_frame..current_frame_index + 1)
+            .filter_map(|frame| self.manager.frame_checksums.get(&frame).map(|checksum| (frame, *checksum)))
+            .collect();
+        if original_checksums.is_empty() {
+            return Ok(progress);
+        }
+
+        resimulate(&mut self.manager, first_checked_frame);
+        for (frame, original) in original_checksums {
+            if let Some(resimulated) = self.manager.frame_checksums.get(&frame).copied() {
+                if resimulated != original {
+                    return Err(RollbackError::NonDeterministic { frame, original, resimulated });
+                }
+            }
+        }
+        Ok(progress)
+    }
+
+    // Record an input. Sync tests usually run a single local player with no network
+    pub fn handle_input(&mut self, frame: usize, id: Id, input: Input) -> Result<()> {
+        self.manager.handle_input(frame, id, input)
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use crate::hash_checksum;
+    use std::cell::Cell;
+
+    #[derive(Debug, Clone, PartialEq, Hash)]
+    struct FrameState {
+        frame: usize,
+        value: u64
+    }
+
+    impl Checksum for FrameState {
+        fn checksum(&self) -> u64 {
+            hash_checksum(self)
+        }
+    }
+
+    fn update(inputs: &FrameInputs<u8, u32>, state: FrameState) -> FrameState {
+        FrameState {
+            frame: state.frame + 1,
+            value: inputs.values().fold(state.value * 3, |value, input| value + *input as u64)
+        }
+    }
+
+    #[test]
+    fn ProgressFrame_DeterministicUpdate_Passes() -> Result<()> {
+        let id = 1u32;
+        let calls = Cell::new(0);
+        let counted_update = |inputs: &FrameInputs<u8, u32>, state: FrameState| {
+            calls.set(calls.get() + 1);
+            update(inputs, state)
+        };
+
+        let mut session = SyncTestSession::new(RollbackStateManager::new(FrameState { frame: 0, value: 0 }, 8), 4);
+        for frame in 1..30 {
+            session.handle_input(frame, id, (frame % 7) as u8)?;
+            session.progress_frame(counted_update)?;
+        }
+
+        // Frames 0 and 1 are simulated by the first progress, then every frame is simulated once and
+        // checked up to four times
+        assert_eq!(session.manager.current_frame_state.frame, 30);
+        assert!(calls.get() > 29 * 4);
+        Ok(())
+    }
+
+    #[test]
+    fn ProgressFrame_NonDeterministicUpdate_ReportsFirstChangedFrame() -> Result<()> {
+        let id = 1u32;
+        // Stands in for hidden state such as an uninitialised value or a map's iteration order
+        let hidden = Cell::new(0);
+        let flaky_update = |inputs: &FrameInputs<u8, u32>, state: FrameState| {
+            let mut state = update(inputs, state);
+            state.value += hidden.get();
+            state
+        };
+
+        let mut session = SyncTestSession::new(RollbackStateManager::new(FrameState { frame: 0, value: 0 }, 8), 3);
+        for frame in 1..9 {
+            session.handle_input(frame, id, 1)?;
+            session.progress_frame(flaky_update)?;
+        }
+
+        // Frames 7 and 8 were first simulated without the hidden value, then checked again with it
+        // after progressing to frame 9
+        hidden.set(1);
+        session.handle_input(9, id, 1)?;
+        match session.progress_frame(flaky_update) {
+            Err(RollbackError::NonDeterministic { frame, .. }) => assert_eq!(frame, 7),
+            result => panic!("Expected a non deterministic frame, got {:?}", result)
+        }
+        Ok(())
+    }
+    // State which can't be cloned, copied through SaveState like an ECS world
+    #[derive(Debug, Hash)]
+    struct World {
+        totals: Vec<u64>
+    }
+
+    impl SaveState for World {
+        fn save_state(&self) -> World {
+            World {
+                totals: self.totals.to_vec()
+            }
+        }
+
+        fn load_state(&mut self, saved: &World) {
+            self.totals.clear();
+            self.totals.extend_from_slice(&saved.totals);
+        }
+    }
+
+    impl Checksum for World {
+        fn checksum(&self) -> u64 {
+            hash_checksum(self)
+        }
+    }
+
+    #[test]
+    fn ProgressFrameMut_NonDeterministicUpdate_ReportsFirstChangedFrame() -> Result<()> {
+        let id = 1u32;
+        let hidden = Cell::new(0);
+        let flaky_update = |inputs: &FrameInputs<u8, u32>, world: &mut World| {
+            for input in inputs.values() {
+                world.totals[*input as usize % 2] += *input as u64 + hidden.get();
+            }
+        };
+
+        let mut session = SyncTestSession::new(RollbackStateManager::new(World { totals: vec![0; 2] }, 8), 3);
+        for frame in 1..9 {
+            session.handle_input(frame, id, 1)?;
+            session.progress_frame_mut(&flaky_update)?;
+        }
+        assert_eq!(session.manager.current_frame_state.totals, vec![0, 8]);
+
+        hidden.set(1);
+        session.handle_input(9, id, 1)?;
+        match session.progress_frame_mut(&flaky_update) {
+            Err(RollbackError::NonDeterministic { frame, .. }) => assert_eq!(frame, 7),
+            result => panic!("Expected a non deterministic frame, got {:?}", result)
+        }
+        Ok(())
+    }
+}
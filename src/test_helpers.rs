@@ -0,0 +1,26 @@
+use core::fmt::Debug;
+use crate::{FrameInputs, InputCodec, InputPredictor, P2PSession, Result, SaveState, Socket, WirePlayerId};
+
+// Update function shared by the session tests. Weights each input by its player so inputs landing
+// on the wrong player change the state, and mixes in the previous state so frame order matters
+pub fn update<Id: Copy + Into<u64>>(inputs: &FrameInputs<u8, Id>, state: u64) -> u64 {
+    inputs.iter().fold(state.wrapping_mul(31), |state, (id, input)| state.wrapping_add(((*id).into() + 1) * (*input as u64 + 1)))
+}
+
+// Add a session's local input once per frame, up to the target frame. Sessions stalled waiting on
+// their peers stay on the same frame, so they don't add any more until they progress
+pub fn add_input_once_per_frame<State, Address, Transport, Codec, Predictor, Id>(
+    session: &mut P2PSession<u8, State, Address, Transport, Codec, Predictor, Id>,
+    input_frame: &mut Option<usize>,
+    target_frame: usize,
+    input: u8
+) -> Result<()>
+        where State: SaveState + Debug, Address: Eq + Clone, Transport: Socket<Address>, Codec: InputCodec<u8>,
+              Predictor: InputPredictor<u8, Id>, Id: WirePlayerId {
+    let current_frame = session.manager.current_frame_index;
+    if session.local_input_frame() <= target_frame && *input_frame != Some(current_frame) {
+        session.add_local_input(input)?;
+        *input_frame = Some(current_frame);
+    }
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+
+// Number of samples frame advantages are averaged over
+pub const SAMPLE_WINDOW: usize = 32;
+// Frames ahead of a peer which are tolerated before recommending a change
+pub const MIN_FRAME_ADVANTAGE: i64 = 2;
+
+// What a session should do to stay in step with its peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSyncRecommendation {
+    InSync,
+    // Running ahead of a peer. Stop advancing for this many frames
+    Wait(usize),
+    // Running behind every peer. Advance this many extra frames to catch up
+    Skip(usize)
+}
+
+impl TimeSyncRecommendation {
+    pub fn from_frames_ahead(frames_ahead: i64) -> TimeSyncRecommendation {
+        if frames_ahead >= MIN_FRAME_ADVANTAGE {
+            TimeSyncRecommendation::Wait(frames_ahead as usize)
+        } else if frames_ahead <= -MIN_FRAME_ADVANTAGE {
+            TimeSyncRecommendation::Skip((-frames_ahead) as usize)
+        } else {
+            TimeSyncRecommendation::InSync
+        }
+    }
+}
+
+fn push_sample(samples: &mut VecDeque<i64>, sample: i64) {
+    if samples.len() == SAMPLE_WINDOW {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+fn average(samples: &VecDeque<i64>) -> i64 {
+    if samples.is_empty() {
+        0
+    } else {
+        samples.iter().sum::<i64>() / samples.len() as i64
+    }
+}
+
+// Tracks round trip time and frame advantage against a single peer. Times are measured in session
+// ticks, which are expected to last about a frame each
+#[derive(Debug, Clone, Default)]
+pub struct TimeSync {
+    // Smoothed time between first sending an input and the peer acknowledging it
+    pub round_trip_ticks: Option<f64>,
+    // Newest frame the peer reported being on, and the tick it was received
+    pub remote_frame: Option<(usize, u64)>,
+    local_advantages: VecDeque<i64>,
+    remote_advantages: VecDeque<i64>,
+    sent_ticks: VecDeque<(usize, u64)>
+}
+
+impl TimeSync {
+    pub fn new() -> TimeSync {
+        TimeSync::default()
+    }
+
+    pub fn record_sent(&mut self, frame: usize, tick: u64) {
+        self.sent_ticks.push_back((frame, tick));
+    }
+
+    pub fn record_ack(&mut self, ack_frame: usize, tick: u64) {
+        let mut newest_sent_tick = None;
+        while let Some((frame, sent_tick)) = self.sent_ticks.front() {
+            if *frame > ack_frame {
+                break;
+            }
+            newest_sent_tick = Some(*sent_tick);
+            self.sent_ticks.pop_front();
+        }
+
+        if let Some(sent_tick) = newest_sent_tick {
+            let sample = tick.saturating_sub(sent_tick) as f64;
+            self.round_trip_ticks = Some(match self.round_trip_ticks {
+                Some(round_trip_ticks) => round_trip_ticks * 0.875 + sample * 0.125,
+                None => sample
+            });
+        }
+    }
+
+    pub fn record_remote(&mut self, frame: usize, frame_advantage: i64, tick: u64) {
+        if self.remote_frame.is_none_or(|(remote_frame, _)| frame >= remote_frame) {
+            self.remote_frame = Some((frame, tick));
+        }
+        push_sample(&mut self.remote_advantages, frame_advantage);
+    }
+
+    // Estimate of the frame the peer is on right now, assuming it advances a frame per tick
+    pub fn estimated_remote_frame(&self, tick: u64) -> Option<i64> {
+        self.remote_frame.map(|(frame, received_tick)| {
+            let one_way_ticks = self.round_trip_ticks.unwrap_or(0.0) / 2.0;
+            frame as i64 + tick.saturating_sub(received_tick) as i64 + one_way_ticks.round() as i64
+        })
+    }
+
+    pub fn sample_local(&mut self, local_frame: usize, tick: u64) {
+        if let Some(remote_frame) = self.estimated_remote_frame(tick) {
+            push_sample(&mut self.local_advantages, remote_frame - local_frame as i64);
+        }
+    }
+
+    // Newest estimate of how many frames the peer is ahead of the local session. This is what gets
+    // sent to the peer, so each side only averages over its own window
+    pub fn latest_local_frame_advantage(&self) -> i64 {
+        self.local_advantages.back().copied().unwrap_or(0)
+    }
+
+    // Average of how many frames the peer is ahead of the local session
+    pub fn local_frame_advantage(&self) -> i64 {
+        average(&self.local_advantages)
+    }
+
+    // Average of how many frames the local session is ahead of the peer, as reported by the peer
+    pub fn remote_frame_advantage(&self) -> i64 {
+        average(&self.remote_advantages)
+    }
+
+    // Whether enough samples have been taken since the last correction to act on the averages
+    pub fn is_settled(&self) -> bool {
+        self.local_advantages.len() == SAMPLE_WINDOW && self.remote_advantages.len() == SAMPLE_WINDOW
+    }
+
+    // Frames the local session is ahead of the peer. Combining both views cancels out any error in
+    // the round trip estimate
+    pub fn frames_ahead(&self) -> i64 {
+        (self.remote_frame_advantage() - self.local_frame_advantage()) / 2
+    }
+
+    // Forget advantage samples after correcting for them so the correction isn't applied twice
+    pub fn clear_samples(&mut self) {
+        self.local_advantages.clear();
+        self.remote_advantages.clear();
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn TimeSync_AckedInputs_EstimatesRemoteFrame() {
+        let mut time_sync = TimeSync::new();
+        time_sync.record_sent(10, 100);
+        time_sync.record_sent(11, 101);
+        time_sync.record_sent(12, 102);
+
+        // Only the newest acknowledged input is sampled
+        time_sync.record_ack(11, 107);
+        assert_eq!(time_sync.round_trip_ticks, Some(6.0));
+        time_sync.record_ack(11, 120);
+        assert_eq!(time_sync.round_trip_ticks, Some(6.0));
+
+        // The peer's frame is extrapolated by the ticks since it was received and half a round trip
+        time_sync.record_remote(40, 0, 107);
+        assert_eq!(time_sync.estimated_remote_frame(109), Some(45));
+        time_sync.sample_local(50, 109);
+        assert_eq!(time_sync.local_frame_advantage(), -5);
+        assert!(!time_sync.is_settled());
+    }
+
+    #[test]
+    fn TimeSyncRecommendation_FromFramesAhead_ToleratesSmallDifferences() {
+        assert_eq!(TimeSyncRecommendation::from_frames_ahead(1), TimeSyncRecommendation::InSync);
+        assert_eq!(TimeSyncRecommendation::from_frames_ahead(-1), TimeSyncRecommendation::InSync);
+        assert_eq!(TimeSyncRecommendation::from_frames_ahead(4), TimeSyncRecommendation::Wait(4));
+        assert_eq!(TimeSyncRecommendation::from_frames_ahead(-3), TimeSyncRecommendation::Skip(3));
+    }
+}
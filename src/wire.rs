@@ -0,0 +1,519 @@
+use std::collections::{BTreeMap, VecDeque};
+#[cfg(feature = "uuid")]
+use uuid::Uuid;
+
+use crate::{DefaultPlayerId, FrameInputs, InputStatus, PlayerId, Result, RollbackError};
+
+// Bumped whenever the layout of encoded batches changes
+pub const WIRE_VERSION: u8 = 3;
+
+// Largest datagram sessions and spectators will receive
+pub const MAX_DATAGRAM_SIZE: usize = 65_536;
+
+// Converts a game's inputs to and from bytes inside an input batch
+pub trait InputCodec<Input> {
+    fn encode(&self, input: &Input, buffer: &mut Vec<u8>);
+    // Read an input from the front of the bytes, advancing them past the input
+    fn decode(&self, bytes: &mut &[u8]) -> Result<Input>;
+}
+
+// Encodes integer inputs as LEB128 varints, so small values take a single byte
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VarintCodec;
+
+macro_rules! impl_varint_codec {
+    ($($int:ty),*) => {
+        $(
+            impl InputCodec<$int> for VarintCodec {
+                fn encode(&self, input: &$int, buffer: &mut Vec<u8>) {
+                    write_varint(buffer, *input as u64);
+                }
+
+                fn decode(&self, bytes: &mut &[u8]) -> Result<$int> {
+                    let value = read_varint(bytes)?;
+                    if value > <$int>::MAX as u64 {
+                        return Err(malformed("input out of range"));
+                    }
+                    Ok(value as $int)
+                }
+            }
+        )*
+    }
+}
+
+impl_varint_codec!(u8, u16, u32, u64);
+
+// Converts player ids to and from bytes inside encoded messages
+pub trait WirePlayerId: PlayerId {
+    fn encode_id(&self, buffer: &mut Vec<u8>);
+    // Read an id from the front of the bytes, advancing them past the id
+    fn decode_id(bytes: &mut &[u8]) -> Result<Self>;
+}
+
+#[cfg(feature = "uuid")]
+impl WirePlayerId for Uuid {
+    fn encode_id(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(self.as_bytes());
+    }
+
+    fn decode_id(bytes: &mut &[u8]) -> Result<Uuid> {
+        if bytes.len() < 16 {
+            return Err(malformed("unexpected end of message"));
+        }
+
+        let (id_bytes, rest) = bytes.split_at(16);
+        *bytes = rest;
+        let mut id = [0; 16];
+        id.copy_from_slice(id_bytes);
+        Ok(Uuid::from_bytes(id))
+    }
+}
+
+// Integer ids such as player slots are encoded as varints, so small ids take a single byte
+macro_rules! impl_varint_player_id {
+    ($($int:ty),*) => {
+        $(
+            impl WirePlayerId for $int {
+                fn encode_id(&self, buffer: &mut Vec<u8>) {
+                    write_varint(buffer, *self as u64);
+                }
+
+                fn decode_id(bytes: &mut &[u8]) -> Result<$int> {
+                    let value = read_varint(bytes)?;
+                    if value > <$int>::MAX as u64 {
+                        return Err(malformed("player id out of range"));
+                    }
+                    Ok(value as $int)
+                }
+            }
+        )*
+    }
+}
+
+impl_varint_player_id!(u8, u16, u32, u64, usize);
+
+fn malformed(reason: &str) -> RollbackError {
+    RollbackError::MalformedMessage {
+        reason: reason.to_string()
+    }
+}
+
+pub fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buffer.push(byte);
+            return;
+        }
+        buffer.push(byte | 0x80);
+    }
+}
+
+pub fn read_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = bytes.split_first().ok_or_else(|| malformed("unexpected end of message"))?;
+        *bytes = rest;
+
+        // The tenth byte only holds the top bit of a u64
+        if shift == 63 && *byte > 1 {
+            return Err(malformed("varint out of range"));
+        }
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(malformed("varint too long"))
+}
+
+// Signed values are zigzag encoded so small negative numbers stay small
+fn write_signed_varint(buffer: &mut Vec<u8>, value: i64) {
+    write_varint(buffer, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+fn read_signed_varint(bytes: &mut &[u8]) -> Result<i64> {
+    let value = read_varint(bytes)?;
+    Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+}
+
+fn encode_status(status: InputStatus) -> u8 {
+    match status {
+        InputStatus::Confirmed => 0,
+        InputStatus::Predicted => 1,
+        InputStatus::Disconnected => 2
+    }
+}
+
+fn decode_status(byte: u8) -> Result<InputStatus> {
+    match byte {
+        0 => Ok(InputStatus::Confirmed),
+        1 => Ok(InputStatus::Predicted),
+        2 => Ok(InputStatus::Disconnected),
+        _ => Err(malformed("unknown input status"))
+    }
+}
+
+fn read_usize(bytes: &mut &[u8]) -> Result<usize> {
+    let value = read_varint(bytes)?;
+    if value > usize::MAX as u64 {
+        return Err(malformed("frame out of range"));
+    }
+    Ok(value as usize)
+}
+
+// A group of inputs sent together, along with the newest frame the sender has received every
+// input for from the receiver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputBatch<Input, Id = DefaultPlayerId> {
+    pub ack_frame: Option<usize>,
+    // Frame the sender was on when sending, used for time synchronisation
+    pub frame: usize,
+    // How many frames the sender thinks the receiver is ahead of it
+    pub frame_advantage: i64,
+    pub inputs: Vec<(usize, Id, Input)>
+}
+
+impl<Input: Clone, Id: WirePlayerId> InputBatch<Input, Id> {
+    pub fn new(ack_frame: Option<usize>) -> InputBatch<Input, Id> {
+        InputBatch {
+            ack_frame,
+            frame: 0,
+            frame_advantage: 0,
+            inputs: Vec::new()
+        }
+    }
+
+    // Layout:
+    //   version: u8
+    //   ack frame + 1, or 0 without an ack: varint
+    //   sender frame: varint
+    //   frame advantage: zigzag varint
+    //   player count: varint
+    //   for each player:
+    //     id: WirePlayerId defined
+    //     input count: varint
+    //     for each input in frame order:
+    //       frame, or frames since the previous input: varint
+    //       input: codec defined
+    pub fn encode<Codec: InputCodec<Input>>(&self, codec: &Codec, buffer: &mut Vec<u8>) {
+        let mut players: BTreeMap<&Id, Vec<(usize, &Input)>> = BTreeMap::new();
+        for (frame, id, input) in self.inputs.iter() {
+            players.entry(id).or_default().push((*frame, input));
+        }
+
+        buffer.push(WIRE_VERSION);
+        write_varint(buffer, self.ack_frame.map_or(0, |frame| frame as u64 + 1));
+        write_varint(buffer, self.frame as u64);
+        write_signed_varint(buffer, self.frame_advantage);
+        write_varint(buffer, players.len() as u64);
+        for (id, mut inputs) in players {
+            inputs.sort_by_key(|(frame, _)| *frame);
+
+            id.encode_id(buffer);
+            write_varint(buffer, inputs.len() as u64);
+            let mut previous_frame = 0;
+            for (frame, input) in inputs {
+                write_varint(buffer, (frame - previous_frame) as u64);
+                codec.encode(input, buffer);
+                previous_frame = frame;
+            }
+        }
+    }
+
+    pub fn decode<Codec: InputCodec<Input>>(mut bytes: &[u8], codec: &Codec) -> Result<InputBatch<Input, Id>> {
+        let bytes = &mut bytes;
+        let (version, rest) = bytes.split_first().ok_or_else(|| malformed("empty message"))?;
+        if *version != WIRE_VERSION {
+            return Err(RollbackError::UnsupportedVersion { version: *version });
+        }
+        *bytes = rest;
+
+        let ack_frame = read_usize(bytes)?.checked_sub(1);
+        let mut batch = InputBatch::new(ack_frame);
+        batch.frame = read_usize(bytes)?;
+        batch.frame_advantage = read_signed_varint(bytes)?;
+        let player_count = read_usize(bytes)?;
+        for _ in 0..player_count {
+            let id = Id::decode_id(bytes)?;
+            let input_count = read_usize(bytes)?;
+            let mut frame = 0usize;
+            for _ in 0..input_count {
+                frame = frame.checked_add(read_usize(bytes)?).ok_or_else(|| malformed("frame out of range"))?;
+                batch.inputs.push((frame, id.clone(), codec.decode(bytes)?));
+            }
+        }
+
+        if !bytes.is_empty() {
+            return Err(malformed("trailing bytes"));
+        }
+        Ok(batch)
+    }
+}
+
+// Consecutive frames of confirmed inputs, sent from a host to its spectators
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfirmedFrames<Input, Id = DefaultPlayerId> {
+    pub first_frame: usize,
+    pub frames: Vec<FrameInputs<Input, Id>>
+}
+
+impl<Input: Clone, Id: WirePlayerId> ConfirmedFrames<Input, Id> {
+    pub fn new(first_frame: usize) -> ConfirmedFrames<Input, Id> {
+        ConfirmedFrames {
+            first_frame,
+            frames: Vec::new()
+        }
+    }
+
+    // Layout:
+    //   version: u8
+    //   first frame: varint
+    //   frame count: varint
+    //   for each frame:
+    //     player count: varint
+    //     for each player in id order:
+    //       id: WirePlayerId defined
+    //       status: u8
+    //       input: codec defined
+    pub fn encode<Codec: InputCodec<Input>>(&self, codec: &Codec, buffer: &mut Vec<u8>) {
+        buffer.push(WIRE_VERSION);
+        write_varint(buffer, self.first_frame as u64);
+        write_varint(buffer, self.frames.len() as u64);
+        for inputs in self.frames.iter() {
+            write_varint(buffer, inputs.len() as u64);
+            for (id, input, status) in inputs.iter_with_status() {
+                id.encode_id(buffer);
+                buffer.push(encode_status(status));
+                codec.encode(input, buffer);
+            }
+        }
+    }
+
+    pub fn decode<Codec: InputCodec<Input>>(mut bytes: &[u8], codec: &Codec) -> Result<ConfirmedFrames<Input, Id>> {
+        let bytes = &mut bytes;
+        let (version, rest) = bytes.split_first().ok_or_else(|| malformed("empty message"))?;
+        if *version != WIRE_VERSION {
+            return Err(RollbackError::UnsupportedVersion { version: *version });
+        }
+        *bytes = rest;
+
+        let mut confirmed_frames = ConfirmedFrames::new(read_usize(bytes)?);
+        let frame_count = read_usize(bytes)?;
+        for _ in 0..frame_count {
+            let player_count = read_usize(bytes)?;
+            let mut inputs = Vec::new();
+            for _ in 0..player_count {
+                let id = Id::decode_id(bytes)?;
+                let (status, rest) = bytes.split_first().ok_or_else(|| malformed("unexpected end of message"))?;
+                *bytes = rest;
+                let status = decode_status(*status)?;
+                inputs.push((id, codec.decode(bytes)?, status));
+            }
+            confirmed_frames.frames.push(FrameInputs::from(inputs));
+        }
+
+        if !bytes.is_empty() {
+            return Err(malformed("trailing bytes"));
+        }
+        Ok(confirmed_frames)
+    }
+}
+
+// Local inputs which the remote peer hasn't acknowledged yet. Every batch resends up to the
+// redundancy limit of the oldest unacknowledged inputs, so no input is skipped when the queue backs
+// up, along with as many of the newest ones, so new inputs keep flowing while acks for a long round
+// trip are still on their way. A lost packet is covered by the ones after it
+#[derive(Debug, Clone)]
+pub struct RedundantInputQueue<Input, Id = DefaultPlayerId> {
+    pub redundancy: usize,
+    unacked_inputs: VecDeque<(usize, Id, Input)>
+}
+
+impl<Input: Clone, Id: WirePlayerId> RedundantInputQueue<Input, Id> {
+    pub fn new(redundancy: usize) -> RedundantInputQueue<Input, Id> {
+        RedundantInputQueue {
+            redundancy: redundancy.max(1),
+            unacked_inputs: VecDeque::new()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.unacked_inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unacked_inputs.is_empty()
+    }
+
+    pub fn push(&mut self, frame: usize, id: Id, input: Input) {
+        self.unacked_inputs.push_back((frame, id, input));
+    }
+
+    // Forget inputs for the acknowledged frame and every frame before it
+    pub fn ack(&mut self, frame: usize) {
+        self.unacked_inputs.retain(|(input_frame, _, _)| *input_frame > frame);
+    }
+
+    // Batch of the unacknowledged inputs to send next
+    pub fn batch(&self, ack_frame: Option<usize>) -> InputBatch<Input, Id> {
+        let mut batch = InputBatch::new(ack_frame);
+        let newest_start = self.unacked_inputs.len().saturating_sub(self.redundancy).max(self.redundancy);
+        batch.inputs = self.unacked_inputs.iter().take(self.redundancy)
+            .chain(self.unacked_inputs.iter().skip(newest_start))
+            .cloned()
+            .collect();
+        batch
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "uuid")]
+    use uuid::Uuid;
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn Encode_Decode_RoundTrips() -> Result<()> {
+        let p1 = Uuid::new_v4();
+        let p2 = Uuid::new_v4();
+        let mut batch = InputBatch::new(Some(41));
+        batch.frame = 97;
+        batch.frame_advantage = -3;
+        batch.inputs.push((100, p1, 3u16));
+        batch.inputs.push((101, p1, 300));
+        batch.inputs.push((98, p2, 0));
+        batch.inputs.push((102, p1, 7));
+
+        let mut buffer = Vec::new();
+        batch.encode(&VarintCodec, &mut buffer);
+        let decoded = InputBatch::decode(&buffer, &VarintCodec)?;
+
+        assert_eq!(decoded.ack_frame, Some(41));
+        assert_eq!(decoded.frame, 97);
+        assert_eq!(decoded.frame_advantage, -3);
+        let mut expected = batch.inputs.clone();
+        let mut actual = decoded.inputs;
+        expected.sort();
+        actual.sort();
+        assert_eq!(actual, expected);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn Encode_ConsecutiveFrames_UsesFrameDeltas() {
+        let mut batch = InputBatch::new(None);
+        for frame in 100_000..100_008 {
+            batch.inputs.push((frame, Uuid::nil(), 1u8));
+        }
+
+        let mut buffer = Vec::new();
+        batch.encode(&VarintCodec, &mut buffer);
+        // Header, id and count, the first absolute frame, then a byte of delta and input each
+        assert_eq!(buffer.len(), 5 + 16 + 1 + 3 + 1 + 7 * 2);
+    }
+
+    #[test]
+    fn Encode_IntegerPlayerIds_UsesVarintIds() -> Result<()> {
+        let mut batch = InputBatch::new(None);
+        batch.inputs.push((0, 3u8, 1u8));
+        batch.inputs.push((0, 200u8, 2u8));
+
+        let mut buffer = Vec::new();
+        batch.encode(&VarintCodec, &mut buffer);
+        // Header, then a two byte id for the player slot above 127, a count, a frame and an input
+        // for each player
+        assert_eq!(buffer.len(), 5 + (1 + 3) + (2 + 3));
+        assert_eq!(InputBatch::<u8, u8>::decode(&buffer, &VarintCodec)?, batch);
+
+        let mut wide_batch = InputBatch::new(None);
+        wide_batch.inputs.push((0, 300u16, 1u8));
+        buffer.clear();
+        wide_batch.encode(&VarintCodec, &mut buffer);
+        assert!(matches!(InputBatch::<u8, u8>::decode(&buffer, &VarintCodec), Err(RollbackError::MalformedMessage { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn Decode_InvalidMessages_Errors() {
+        let mut batch = InputBatch::new(None);
+        batch.inputs.push((5, 1u32, 300u32));
+        let mut buffer = Vec::new();
+        batch.encode(&VarintCodec, &mut buffer);
+
+        let mut wrong_version = buffer.clone();
+        wrong_version[0] = WIRE_VERSION + 1;
+        assert!(matches!(InputBatch::<u32, u32>::decode(&wrong_version, &VarintCodec), Err(RollbackError::UnsupportedVersion { .. })));
+        assert!(matches!(InputBatch::<u32, u32>::decode(&buffer[..buffer.len() - 1], &VarintCodec), Err(RollbackError::MalformedMessage { .. })));
+        assert!(matches!(InputBatch::<u8, u32>::decode(&buffer, &VarintCodec), Err(RollbackError::MalformedMessage { .. })));
+    }
+
+    #[test]
+    fn ReadVarint_TenByteValues_RejectsOverflow() -> Result<()> {
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, u64::MAX);
+        assert_eq!(buffer.len(), 10);
+        assert_eq!(read_varint(&mut buffer.as_slice())?, u64::MAX);
+
+        *buffer.last_mut().unwrap() = 0x02;
+        assert!(matches!(read_varint(&mut buffer.as_slice()), Err(RollbackError::MalformedMessage { .. })));
+        *buffer.last_mut().unwrap() = 0x81;
+        assert!(matches!(read_varint(&mut buffer.as_slice()), Err(RollbackError::MalformedMessage { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn ConfirmedFrames_Encode_Decode_RoundTrips() -> Result<()> {
+        let mut confirmed_frames = ConfirmedFrames::new(12);
+        confirmed_frames.frames.push(FrameInputs::new());
+        confirmed_frames.frames.push(vec![(1u32, 5u32, InputStatus::Confirmed), (2, 70_000, InputStatus::Disconnected)].into_iter().collect());
+
+        let mut buffer = Vec::new();
+        confirmed_frames.encode(&VarintCodec, &mut buffer);
+        assert_eq!(ConfirmedFrames::decode(&buffer, &VarintCodec)?, confirmed_frames);
+        assert!(matches!(ConfirmedFrames::<u32, u32>::decode(&buffer[..buffer.len() - 1], &VarintCodec), Err(RollbackError::MalformedMessage { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn RedundantInputQueue_Batch_ResendsUnackedInputs() {
+        let id = 1u32;
+        let mut queue = RedundantInputQueue::new(3);
+        for frame in 0..5 {
+            queue.push(frame, id, frame as u8);
+        }
+
+        let frames: Vec<usize> = queue.batch(None).inputs.iter().map(|(frame, _, _)| *frame).collect();
+        assert_eq!(frames, vec![0, 1, 2, 3, 4]);
+
+        queue.ack(1);
+        let frames: Vec<usize> = queue.batch(None).inputs.iter().map(|(frame, _, _)| *frame).collect();
+        assert_eq!(frames, vec![2, 3, 4]);
+
+        // Once the queue backs up the newest inputs are sent alongside the oldest, skipping the
+        // ones in between
+        for frame in 5..10 {
+            queue.push(frame, id, frame as u8);
+        }
+        let frames: Vec<usize> = queue.batch(None).inputs.iter().map(|(frame, _, _)| *frame).collect();
+        assert_eq!(frames, vec![2, 3, 4, 7, 8, 9]);
+
+        queue.ack(5);
+        let frames: Vec<usize> = queue.batch(None).inputs.iter().map(|(frame, _, _)| *frame).collect();
+        assert_eq!(frames, vec![6, 7, 8, 9]);
+
+        queue.ack(8);
+        assert_eq!(queue.len(), 1);
+        let batch = queue.batch(Some(7));
+        assert_eq!(batch.ack_frame, Some(7));
+        assert_eq!(batch.inputs, vec![(9, id, 9)]);
+    }
+}